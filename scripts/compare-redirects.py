@@ -42,6 +42,10 @@ def main(sh1, sh2):
         "echo j >file; cat <file >&2",
         "echo k >foo >bar >baz",
         "echo l >file | cat",
+        "echo m &>file",
+        "echo n &>>file; echo n &>>file",
+        "sh -c 'echo o; echo o >&2' &>file",
+        "sh -c 'echo p; echo p >&2' |& cat",
     )
 
     sh1_prog = os.path.basename(sh1)
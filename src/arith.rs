@@ -0,0 +1,266 @@
+use std::fmt;
+use std::str;
+
+use crate::environment::Environment;
+use crate::Result;
+
+/// Evaluates the integer expression inside `$(( ... ))`. Variables are
+/// referenced by name, and count as zero when unset or empty.
+pub fn evaluate(expr: &[u8], env: &Environment) -> Result<i64> {
+    let mut parser = Parser {
+        tokens: tokenize(expr)?,
+        pos: 0,
+    };
+    let expr = parser.parse_expr(0)?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        bail!("arithmetic syntax error near: {}", token);
+    }
+    expr.eval(env)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(&'static str),
+    LeftParen,
+    RightParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(ref name) => write!(f, "{}", name),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+        }
+    }
+}
+
+/// Operators, longest first so that `<=` isn't read as `<`.
+const OPERATORS: &[&str] = &[
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!",
+];
+
+fn tokenize(expr: &[u8]) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < expr.len() {
+        let byte = expr[i];
+        if byte.is_ascii_whitespace() {
+            i += 1;
+        } else if byte.is_ascii_digit() {
+            let len = expr[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+            let digits = str::from_utf8(&expr[i..i + len]).unwrap();
+            match digits.parse() {
+                Ok(n) => tokens.push(Token::Number(n)),
+                Err(_) => bail!("number out of range: {}", digits),
+            }
+            i += len;
+        } else if byte.is_ascii_alphabetic() || byte == b'_' {
+            let len = expr[i..]
+                .iter()
+                .take_while(|&&b| b.is_ascii_alphanumeric() || b == b'_')
+                .count();
+            let name = str::from_utf8(&expr[i..i + len]).unwrap();
+            tokens.push(Token::Name(name.into()));
+            i += len;
+        } else if byte == b'(' {
+            tokens.push(Token::LeftParen);
+            i += 1;
+        } else if byte == b')' {
+            tokens.push(Token::RightParen);
+            i += 1;
+        } else {
+            match OPERATORS
+                .iter()
+                .find(|op| expr[i..].starts_with(op.as_bytes()))
+            {
+                Some(op) => {
+                    tokens.push(Token::Op(op));
+                    i += op.len();
+                }
+                None => bail!(
+                    "invalid arithmetic operator: {}",
+                    String::from_utf8_lossy(&expr[i..])
+                ),
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Number(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, env: &Environment) -> Result<i64> {
+        match *self {
+            Expr::Number(n) => Ok(n),
+            Expr::Var(ref name) => {
+                let value = env.get(name).unwrap_or_default().to_string_lossy();
+                let value = value.trim();
+                if value.is_empty() {
+                    return Ok(0);
+                }
+                match value.parse() {
+                    Ok(n) => Ok(n),
+                    Err(_) => bail!("{}: not a number: {}", name, value),
+                }
+            }
+            Expr::Unary(op, ref operand) => {
+                let operand = operand.eval(env)?;
+                match op {
+                    "-" => checked(operand.checked_neg()),
+                    "!" => Ok((operand == 0) as i64),
+                    _ => Ok(operand),
+                }
+            }
+            Expr::Binary("&&", ref lhs, ref rhs) => {
+                Ok((lhs.eval(env)? != 0 && rhs.eval(env)? != 0) as i64)
+            }
+            Expr::Binary("||", ref lhs, ref rhs) => {
+                Ok((lhs.eval(env)? != 0 || rhs.eval(env)? != 0) as i64)
+            }
+            Expr::Binary(op, ref lhs, ref rhs) => {
+                let (lhs, rhs) = (lhs.eval(env)?, rhs.eval(env)?);
+                match op {
+                    "+" => checked(lhs.checked_add(rhs)),
+                    "-" => checked(lhs.checked_sub(rhs)),
+                    "*" => checked(lhs.checked_mul(rhs)),
+                    "/" | "%" if rhs == 0 => bail!("division by zero"),
+                    "/" => checked(lhs.checked_div(rhs)),
+                    "%" => checked(lhs.checked_rem(rhs)),
+                    "==" => Ok((lhs == rhs) as i64),
+                    "!=" => Ok((lhs != rhs) as i64),
+                    "<" => Ok((lhs < rhs) as i64),
+                    "<=" => Ok((lhs <= rhs) as i64),
+                    ">" => Ok((lhs > rhs) as i64),
+                    ">=" => Ok((lhs >= rhs) as i64),
+                    _ => unreachable!("unknown operator: {}", op),
+                }
+            }
+        }
+    }
+}
+
+fn checked(result: Option<i64>) -> Result<i64> {
+    match result {
+        Some(n) => Ok(n),
+        None => bail!("arithmetic overflow"),
+    }
+}
+
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "==" | "!=" => Some(3),
+        "<" | "<=" | ">" | ">=" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        _ => None,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// Parses binary operators that bind at least as tightly as `min`.
+    fn parse_expr(&mut self, min: u8) -> Result<Expr> {
+        let mut lhs = self.parse_unary()?;
+        while let Some(&Token::Op(op)) = self.tokens.get(self.pos) {
+            match precedence(op) {
+                Some(prec) if prec >= min => {
+                    self.pos += 1;
+                    let rhs = self.parse_expr(prec + 1)?;
+                    lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+                }
+                _ => break,
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => Ok(Expr::Var(name)),
+            Some(Token::Op(op)) if op == "-" || op == "+" || op == "!" => {
+                Ok(Expr::Unary(op, Box::new(self.parse_unary()?)))
+            }
+            Some(Token::LeftParen) => {
+                let expr = self.parse_expr(0)?;
+                match self.next() {
+                    Some(Token::RightParen) => Ok(expr),
+                    _ => bail!("arithmetic syntax error: missing ')'"),
+                }
+            }
+            Some(token) => bail!("arithmetic syntax error near: {}", token),
+            None => bail!("arithmetic syntax error: missing operand"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str) -> Result<i64> {
+        let mut env = Environment::new();
+        env.set("ten".into(), "10".into());
+        env.set("empty".into(), "".into());
+        env.set("word".into(), "abc".into());
+        evaluate(expr.as_bytes(), &env)
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval("10 - 4 - 3").unwrap(), 3);
+        assert_eq!(eval("17 / 5 + 17 % 5").unwrap(), 5);
+        assert_eq!(eval("-3 + +1").unwrap(), -2);
+        assert_eq!(eval("2 < 3 && 3 <= 3 && !(4 > 5) && 1 != 2").unwrap(), 1);
+        assert_eq!(eval("0 || 2 >= 3").unwrap(), 0);
+        assert_eq!(eval("1 == 1 == 1").unwrap(), 1);
+        assert_eq!(eval("0 && 1 / 0").unwrap(), 0);
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval("ten * ten").unwrap(), 100);
+        assert_eq!(eval("unset + empty").unwrap(), 0);
+        assert!(eval("word + 1").is_err());
+    }
+
+    #[test]
+    fn errors() {
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("1 % 0").is_err());
+        assert!(eval("9223372036854775807 + 1").is_err());
+        assert!(eval("-9223372036854775807 - 2").is_err());
+        assert!(eval("99999999999999999999").is_err());
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("1 ^ 2").is_err());
+        assert!(eval("").is_err());
+    }
+}
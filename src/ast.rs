@@ -1,4 +1,8 @@
+use std::fmt;
+use std::rc::Rc;
+
 use crate::command::Command;
+use crate::redirect::Redirect;
 use crate::word::Word;
 
 pub type Program = Block;
@@ -8,37 +12,140 @@ pub type Block = Vec<Stmt>;
 pub enum Stmt {
     If(IfStmt),
     While(WhileStmt),
+    /// A loop like `while`, except that it runs until the test succeeds.
+    Until(WhileStmt),
+    For(ForStmt),
+    Match(MatchStmt),
+    Function(FunctionStmt),
+    Break,
+    Continue,
+    Return(Option<Word>),
     Export(Vec<Exportable>),
     Assignment(Vec<NameValuePair>),
     Command(Command),
+    AndOr(AndOrList),
+    /// A command or list ending in `&`, which runs without being waited for.
+    Background(AndOrList),
+    /// Statements in braces, run by the shell itself.
+    Group(GroupStmt),
+    /// Statements in parentheses, run in a copy of the shell so that changes
+    /// to its state don't last.
+    Subshell(GroupStmt),
+    /// Statements joined by `|` where at least one isn't a plain command, as
+    /// in `cat hosts | while read host { ... }`. Pipelines of commands are
+    /// chained together by `Command` instead.
+    Pipeline(Vec<Stmt>),
 }
 
 #[derive(Debug, PartialEq)]
 pub struct IfStmt {
-    pub test: Command,
+    pub test: AndOrList,
     pub consequent: Block,
     pub alternate: Option<Block>,
+    /// Applied around the whole statement, as in `if ... { } > report.txt`.
+    pub redirects: Vec<Redirect<Word>>,
 }
 
 impl IfStmt {
-    pub fn new(test: Command, consequent: Block, alternate: Option<Block>) -> Self {
+    pub fn new<T>(test: T, consequent: Block, alternate: Option<Block>) -> Self
+    where
+        T: Into<AndOrList>,
+    {
         Self {
-            test,
+            test: test.into(),
             consequent,
             alternate,
+            redirects: Vec::new(),
         }
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct WhileStmt {
-    pub test: Command,
+    pub test: AndOrList,
     pub body: Block,
+    /// Applied around the whole loop, as in `while read line { } < hosts`.
+    pub redirects: Vec<Redirect<Word>>,
 }
 
 impl WhileStmt {
-    pub fn new(test: Command, body: Block) -> Self {
-        Self { test, body }
+    pub fn new<T>(test: T, body: Block) -> Self
+    where
+        T: Into<AndOrList>,
+    {
+        Self {
+            test: test.into(),
+            body,
+            redirects: Vec::new(),
+        }
+    }
+}
+
+/// Runs the first arm with a pattern matching the word, as in
+/// `match $1 { start | restart { ... } * { ... } }`.
+#[derive(Debug, PartialEq)]
+pub struct MatchStmt {
+    pub word: Word,
+    pub arms: Vec<MatchArm>,
+}
+
+impl MatchStmt {
+    pub fn new(word: Word, arms: Vec<MatchArm>) -> Self {
+        Self { word, arms }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct MatchArm {
+    pub patterns: Vec<Word>,
+    pub body: Block,
+}
+
+impl MatchArm {
+    pub fn new(patterns: Vec<Word>, body: Block) -> Self {
+        Self { patterns, body }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ForStmt {
+    pub name: Word,
+    pub words: Vec<Word>,
+    pub body: Block,
+}
+
+impl ForStmt {
+    pub fn new(name: Word, words: Vec<Word>, body: Block) -> Self {
+        Self { name, words, body }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionStmt {
+    pub name: Word,
+    pub body: Rc<Block>,
+}
+
+impl FunctionStmt {
+    pub fn new(name: Word, body: Block) -> Self {
+        Self {
+            name,
+            body: Rc::new(body),
+        }
+    }
+}
+
+/// A block with redirects that apply to all of it, as in
+/// `{ make; make test } >build.log`.
+#[derive(Debug, PartialEq)]
+pub struct GroupStmt {
+    pub body: Block,
+    pub redirects: Vec<Redirect<Word>>,
+}
+
+impl GroupStmt {
+    pub fn new(body: Block, redirects: Vec<Redirect<Word>>) -> Self {
+        Self { body, redirects }
     }
 }
 
@@ -65,3 +172,88 @@ impl NameValuePair {
         Self { name, value }
     }
 }
+
+/// Pipelines joined by `&&` and `||`, evaluated left to right with equal
+/// precedence. Each one is a `Command`, a `Pipeline` of statements or a
+/// compound statement like a `Group` or `Subshell`.
+#[derive(Debug, PartialEq)]
+pub struct AndOrList {
+    pub first: Box<Stmt>,
+    pub rest: Vec<(LogicalOp, Stmt)>,
+}
+
+impl AndOrList {
+    pub fn new<T>(first: T, rest: Vec<(LogicalOp, Stmt)>) -> Self
+    where
+        T: Into<Stmt>,
+    {
+        Self {
+            first: Box::new(first.into()),
+            rest,
+        }
+    }
+}
+
+impl From<Command> for AndOrList {
+    fn from(command: Command) -> Self {
+        Self::new(command, Vec::new())
+    }
+}
+
+impl From<Command> for Stmt {
+    fn from(command: Command) -> Self {
+        Stmt::Command(command)
+    }
+}
+
+impl fmt::Display for AndOrList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Element(&self.first))?;
+        for (op, stmt) in &self.rest {
+            write!(f, " {} {}", op, Element(stmt))?;
+        }
+        Ok(())
+    }
+}
+
+/// Describes a pipeline of statements for the job table, as in
+/// `cat hosts | while ...`.
+pub fn describe_pipeline(stmts: &[Stmt]) -> String {
+    let elements: Vec<_> = stmts.iter().map(|stmt| Element(stmt).to_string()).collect();
+    elements.join(" | ")
+}
+
+/// A statement as the job table shows it. Only commands are spelled out, and
+/// compound statements are left at their keyword.
+struct Element<'a>(&'a Stmt);
+
+impl<'a> fmt::Display for Element<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self.0 {
+            Stmt::Command(ref command) => write!(f, "{}", command),
+            Stmt::Group(_) => write!(f, "{{ ... }}"),
+            Stmt::Subshell(_) => write!(f, "( ... )"),
+            Stmt::If(_) => write!(f, "if ..."),
+            Stmt::While(_) => write!(f, "while ..."),
+            Stmt::Until(_) => write!(f, "until ..."),
+            Stmt::For(_) => write!(f, "for ..."),
+            Stmt::Match(_) => write!(f, "match ..."),
+            _ => write!(f, "..."),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+impl fmt::Display for LogicalOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogicalOp::And => write!(f, "&&"),
+            LogicalOp::Or => write!(f, "||"),
+        }
+    }
+}
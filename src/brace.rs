@@ -0,0 +1,237 @@
+use std::convert::TryFrom;
+use std::str;
+
+use crate::word::{self, find_closing_paren};
+
+/// Expands the braces in an unquoted word, returning every word it stands
+/// for in order. `a{b,c}d` is `abd acd`, `{1..3}` is `1 2 3` and `{a..e..2}`
+/// is `a c e`. Braces that don't form a list or a sequence are left as is,
+/// and so is a word that would expand to more than `MAX_WORDS` words.
+pub fn expand(word: &[u8]) -> Vec<Vec<u8>> {
+    let mut words = Vec::new();
+    if expand_into(word, &mut words) {
+        words
+    } else {
+        vec![word.to_vec()]
+    }
+}
+
+/// The most words that brace expansion may turn a word into, rather than
+/// running out of memory on something like `{1..100000}{1..100000}`.
+const MAX_WORDS: usize = 100_000;
+
+/// Adds the words that `word` expands to onto `words`, or returns false once
+/// there'd be more than `MAX_WORDS` of them.
+fn expand_into(word: &[u8], words: &mut Vec<Vec<u8>>) -> bool {
+    let mut i = 0;
+    while i < word.len() {
+        match word[i] {
+            b'\\' => i += 1,
+            b'$' if word.get(i + 1) == Some(&b'(') => {
+                if let Some(len) = find_closing_paren(&word[i + 2..]) {
+                    i += len + 2;
+                }
+            }
+            b'$' if word.get(i + 1) == Some(&b'{') => {
+                if let Some(len) = word::find_closing_brace(&word[i + 2..]) {
+                    i += len + 2;
+                }
+            }
+            b'{' => {
+                if let Some(len) = find_closing_brace(&word[i..]) {
+                    if let Some(alternatives) = parse(&word[i + 1..i + len]) {
+                        let (prefix, suffix) = (&word[..i], &word[i + len + 1..]);
+                        for alternative in alternatives {
+                            let mut next = prefix.to_vec();
+                            next.extend_from_slice(&alternative);
+                            next.extend_from_slice(suffix);
+                            if !expand_into(&next, words) {
+                                return false;
+                            }
+                        }
+                        return true;
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    if words.len() == MAX_WORDS {
+        return false;
+    }
+    words.push(word.to_vec());
+    true
+}
+
+/// Finds the `}` matching the `{` at the start of `bytes`, skipping nested
+/// braces and escapes.
+fn find_closing_brace(bytes: &[u8]) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Parses the inside of a brace expression into its alternatives.
+fn parse(inner: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let alternatives = split_list(inner);
+    if alternatives.len() > 1 {
+        Some(alternatives)
+    } else {
+        parse_sequence(inner)
+    }
+}
+
+/// Splits a list like `a,b{c,d}` at its top level commas.
+fn split_list(inner: &[u8]) -> Vec<Vec<u8>> {
+    let mut alternatives = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < inner.len() {
+        match inner[i] {
+            b'\\' => i += 1,
+            b'{' => depth += 1,
+            b'}' => depth -= 1,
+            b',' if depth == 0 => {
+                alternatives.push(inner[start..i].to_vec());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    alternatives.push(inner[start..].to_vec());
+    alternatives
+}
+
+/// Parses a sequence like `1..10`, `-3..3..2` or `a..z`.
+fn parse_sequence(inner: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let inner = str::from_utf8(inner).ok()?;
+    let parts: Vec<_> = inner.split("..").collect();
+    let step = match parts.len() {
+        2 => 1,
+        3 => parts[2].parse::<i64>().ok()?.checked_abs()?.max(1),
+        _ => return None,
+    };
+    let (start, end) = (parts[0], parts[1]);
+
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        // `{01..10}` pads every number to the same width.
+        let width = if is_zero_padded(start) || is_zero_padded(end) {
+            start.len().max(end.len())
+        } else {
+            0
+        };
+        let values = sequence(first, last, step)?;
+        return Some(
+            values
+                .map(|n| format!("{:01$}", n, width).into_bytes())
+                .collect(),
+        );
+    }
+
+    match (start.as_bytes(), end.as_bytes()) {
+        (&[first], &[last]) if first.is_ascii_alphabetic() && last.is_ascii_alphabetic() => {
+            let values = sequence(i64::from(first), i64::from(last), step)?;
+            Some(values.map(|n| vec![n as u8]).collect())
+        }
+        _ => None,
+    }
+}
+
+fn sequence(first: i64, last: i64, step: i64) -> Option<impl Iterator<Item = i64>> {
+    let len = (i128::from(last) - i128::from(first)).abs() / i128::from(step);
+    let len = usize::try_from(len).ok()?.checked_add(1)?;
+    // Don't build a sequence too long to expand anyway.
+    if len > MAX_WORDS {
+        return None;
+    }
+    let step = if first <= last { step } else { -step };
+    Some((0..len).map(move |i| first + i as i64 * step))
+}
+
+fn is_zero_padded(number: &str) -> bool {
+    let digits = number.trim_start_matches(['-', '+']);
+    digits.len() > 1 && digits.starts_with('0')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_str(word: &str) -> Vec<String> {
+        expand(word.as_bytes())
+            .into_iter()
+            .map(|word| String::from_utf8(word).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(expand_str("file.{c,h}"), vec!["file.c", "file.h"]);
+        assert_eq!(expand_str("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_str("a{b,c{d,e}}f"), vec!["abf", "acdf", "acef"]);
+        assert_eq!(expand_str("{,old.}conf"), vec!["conf", "old.conf"]);
+        assert_eq!(expand_str("{{a,b}"), vec!["{a", "{b"]);
+    }
+
+    #[test]
+    fn sequences() {
+        assert_eq!(
+            expand_str("img{1..3}.png"),
+            vec!["img1.png", "img2.png", "img3.png"]
+        );
+        assert_eq!(expand_str("{3..1}"), vec!["3", "2", "1"]);
+        assert_eq!(expand_str("{-2..2..2}"), vec!["-2", "0", "2"]);
+        assert_eq!(expand_str("{08..10}"), vec!["08", "09", "10"]);
+        assert_eq!(expand_str("{a..e..2}"), vec!["a", "c", "e"]);
+        assert_eq!(expand_str("{z..x}"), vec!["z", "y", "x"]);
+    }
+
+    #[test]
+    fn long_sequences() {
+        assert_eq!(expand_str("{1..100000}").len(), MAX_WORDS);
+        assert_eq!(expand_str("{0..100000}"), vec!["{0..100000}"]);
+        assert_eq!(expand_str("{1..100000000}"), vec!["{1..100000000}"]);
+        assert_eq!(expand_str("{0..1000000..100}").len(), 10_001);
+    }
+
+    #[test]
+    fn too_many_words() {
+        assert_eq!(expand_str("{1..1000}{1..100}").len(), MAX_WORDS);
+        assert_eq!(
+            expand_str("{1..100000}{1..100000}"),
+            vec!["{1..100000}{1..100000}"]
+        );
+        assert_eq!(expand_str("x{1..1000}{0..100}"), vec!["x{1..1000}{0..100}"]);
+        let word = "{a,b}".repeat(20);
+        assert_eq!(expand_str(&word), vec![word]);
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(expand_str("{}"), vec!["{}"]);
+        assert_eq!(expand_str("{a}"), vec!["{a}"]);
+        assert_eq!(expand_str("{a,b"), vec!["{a,b"]);
+        assert_eq!(expand_str("{1..b}"), vec!["{1..b}"]);
+        assert_eq!(expand_str(r"\{a,b}"), vec![r"\{a,b}"]);
+        assert_eq!(expand_str("{a\\,b}"), vec!["{a\\,b}"]);
+        assert_eq!(expand_str("${a,b}"), vec!["${a,b}"]);
+        assert_eq!(expand_str("$(echo {a,b})"), vec!["$(echo {a,b})"]);
+    }
+}
@@ -1,9 +1,11 @@
 use std::borrow::Cow;
 use std::ffi::{CString, OsStr};
+use std::fmt;
 use std::os::unix::ffi::OsStrExt;
 
 use crate::ast::NameValuePair;
 use crate::environment::Environment;
+use crate::interpreter::Interpreter;
 use crate::redirect::Redirect;
 use crate::word::Word;
 use crate::Result;
@@ -15,6 +17,7 @@ pub struct Command {
     redirects: Vec<Redirect<Word>>,
     env: Vec<NameValuePair>,
     pipeline: Option<Box<Command>>,
+    negated: bool,
 }
 
 impl Command {
@@ -25,6 +28,7 @@ impl Command {
             redirects: Vec::new(),
             env: Vec::new(),
             pipeline: None,
+            negated: false,
         }
     }
 
@@ -48,34 +52,33 @@ impl Command {
         self.pipeline = Some(Box::new(pipeline));
     }
 
-    pub fn expand(&self, environment: &Environment) -> Result<ExpandedCommand> {
-        let name = self.name.expand(environment)?;
+    /// Inverts the exit status of the whole pipeline, as in `! grep -q foo`.
+    pub fn negate(&mut self) {
+        self.negated = true;
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+
+    pub fn expand(&self, shell: &mut Interpreter) -> Result<ExpandedCommand<'_>> {
+        let name = self.name.expand(shell)?;
 
         let mut arguments = Vec::new();
         for argument in &self.arguments {
-            arguments.push(argument.expand(environment)?);
+            arguments.extend(argument.expand_fields(shell)?);
         }
 
         let mut redirects = Vec::new();
         for redirect in &self.redirects {
-            redirects.push(match *redirect {
-                Redirect::InFile(ref path) => Redirect::InFile(path.expand(environment)?),
-                Redirect::OutErr => Redirect::OutErr,
-                Redirect::OutFile(ref path, mode) => {
-                    Redirect::OutFile(path.expand(environment)?, mode)
-                }
-                Redirect::ErrOut => Redirect::ErrOut,
-                Redirect::ErrFile(ref path, mode) => {
-                    Redirect::ErrFile(path.expand(environment)?, mode)
-                }
-            });
+            redirects.push(redirect.try_map(|word| word.expand(shell))?);
         }
 
         let mut env = Vec::new();
         for pair in &self.env {
             env.push((
                 Cow::Borrowed(pair.name.value.as_ref()),
-                pair.value.expand(environment)?,
+                pair.value.expand(shell)?,
             ));
         }
 
@@ -85,13 +88,35 @@ impl Command {
             redirects,
             env,
             pipeline: match self.pipeline {
-                Some(ref cmd) => Some(Box::new(cmd.expand(environment)?)),
+                Some(ref cmd) => Some(Box::new(cmd.expand(shell)?)),
                 None => None,
             },
         })
     }
 }
 
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for pair in &self.env {
+            write!(f, "{}={} ", pair.name, pair.value)?;
+        }
+        write!(f, "{}", self.name)?;
+        for argument in &self.arguments {
+            write!(f, " {}", argument)?;
+        }
+        for redirect in &self.redirects {
+            write!(f, " {}", redirect)?;
+        }
+        if let Some(ref pipeline) = self.pipeline {
+            write!(f, " | {}", pipeline)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ExpandedCommand<'a> {
     name: Cow<'a, OsStr>,
@@ -129,15 +154,15 @@ impl<'a> ExpandedCommand<'a> {
         &self.name
     }
 
-    pub fn arguments(&self) -> &[Cow<OsStr>] {
+    pub fn arguments(&self) -> &[Cow<'_, OsStr>] {
         &self.arguments
     }
 
-    pub fn redirects(&self) -> &[Redirect<Cow<OsStr>>] {
+    pub fn redirects(&self) -> &[Redirect<Cow<'_, OsStr>>] {
         &self.redirects
     }
 
-    pub fn pipeline(&self) -> Option<&ExpandedCommand> {
+    pub fn pipeline(&self) -> Option<&ExpandedCommand<'_>> {
         self.pipeline.as_ref().map(AsRef::as_ref)
     }
 }
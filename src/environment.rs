@@ -2,13 +2,12 @@ use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::env;
 use std::ffi::{OsStr, OsString};
+use std::mem;
 use std::path::Path;
 
-use crate::ast::{Exportable, NameValuePair};
-use crate::Result;
-
 pub struct Environment {
     values: HashMap<OsString, Var>,
+    positional: Vec<OsString>,
 }
 
 impl Environment {
@@ -17,6 +16,7 @@ impl Environment {
             values: env::vars_os()
                 .map(|(name, value)| (name, Var::new(value, true)))
                 .collect(),
+            positional: Vec::new(),
         }
     }
 
@@ -24,30 +24,38 @@ impl Environment {
         self.values.get(name.as_ref()).map(|var| var.value.as_ref())
     }
 
-    pub fn assign(&mut self, pair: &NameValuePair) -> Result<()> {
-        let value = pair.value.expand(self)?.into_owned();
-        match self.values.entry(pair.name.to_os_string()) {
+    /// Sets a variable's value, keeping it exported if it already was.
+    pub fn set(&mut self, name: OsString, value: OsString) {
+        match self.values.entry(name) {
             Entry::Occupied(mut entry) => entry.get_mut().value = value,
             Entry::Vacant(entry) => {
                 entry.insert(Var::new(value, false));
             }
         }
-        Ok(())
     }
 
-    pub fn export(&mut self, exportable: &Exportable) -> Result<()> {
-        if let Some(ref value) = exportable.value {
-            let var = Var::new(value.expand(self)?.into_owned(), true);
-            self.values.insert(exportable.name.to_os_string(), var);
+    pub fn export(&mut self, name: OsString, value: Option<OsString>) {
+        if let Some(value) = value {
+            self.values.insert(name, Var::new(value, true));
         } else {
-            match self.values.entry(exportable.name.to_os_string()) {
+            match self.values.entry(name) {
                 Entry::Occupied(mut entry) => entry.get_mut().is_exported = true,
                 Entry::Vacant(entry) => {
                     entry.insert(Var::new(OsString::from(""), true));
                 }
             }
         }
-        Ok(())
+    }
+
+    /// The positional parameters `$1`..`$N`.
+    pub fn positional(&self) -> &[OsString] {
+        &self.positional
+    }
+
+    /// Replaces the positional parameters, returning the previous ones so
+    /// they can be restored when a function returns.
+    pub fn set_positional(&mut self, arguments: Vec<OsString>) -> Vec<OsString> {
+        mem::replace(&mut self.positional, arguments)
     }
 
     pub fn home(&self) -> &Path {
@@ -0,0 +1,366 @@
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::Path;
+
+/// What pathname expansion does with a pattern that matches nothing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NoMatch {
+    /// Pass the pattern through as a literal argument.
+    Literal,
+    /// Expand to no arguments at all.
+    Empty,
+    /// Fail the expansion with an error.
+    Error,
+}
+
+/// A configurable pathname expander.
+#[derive(Clone, Copy, Debug)]
+pub struct Glob {
+    pub no_match: NoMatch,
+    pub hidden: bool,
+}
+
+impl Glob {
+    pub fn new() -> Self {
+        Self {
+            no_match: NoMatch::Literal,
+            hidden: false,
+        }
+    }
+
+    pub fn no_match(mut self, no_match: NoMatch) -> Self {
+        self.no_match = no_match;
+        self
+    }
+
+    /// Whether patterns match hidden files without an explicit leading `.`.
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Expands a pathname pattern relative to `cwd`, returning the sorted
+    /// paths that match. Each `/` separated component may contain `*`, `?`
+    /// and `[...]`, and a `**` component matches any number of directories.
+    pub fn expand(&self, cwd: &Path, pattern: &[u8]) -> Vec<OsString> {
+        let (mut paths, pattern) = if pattern.starts_with(b"/") {
+            (vec![b"/".to_vec()], &pattern[1..])
+        } else {
+            (vec![Vec::new()], pattern)
+        };
+
+        let components: Vec<_> = pattern.split(|&b| b == b'/').collect();
+        for (i, &component) in components.iter().enumerate() {
+            let is_last = i == components.len() - 1;
+            let mut next = Vec::new();
+            for path in paths {
+                if component == b"**" {
+                    next.push(path.clone());
+                    self.walk(cwd, &path, is_last, &mut next);
+                } else if is_pattern(component) {
+                    self.match_entries(cwd, &path, component, &mut next);
+                } else {
+                    next.push(join(&path, &unescape(component)));
+                }
+            }
+            paths = next;
+        }
+
+        let mut paths: Vec<_> = paths
+            .into_iter()
+            .filter(|path| !path.is_empty())
+            .filter(|path| fs::symlink_metadata(cwd.join(OsStr::from_bytes(path))).is_ok())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths.into_iter().map(OsString::from_vec).collect()
+    }
+
+    fn match_entries(&self, cwd: &Path, path: &[u8], component: &[u8], paths: &mut Vec<Vec<u8>>) {
+        for name in self.read_dir(cwd, path, component.starts_with(b".")) {
+            if matches(component, &name) {
+                paths.push(join(path, &name));
+            }
+        }
+    }
+
+    /// Collects every directory below `path`, and with `files` every other
+    /// entry too. Symbolic links to directories aren't followed.
+    fn walk(&self, cwd: &Path, path: &[u8], files: bool, paths: &mut Vec<Vec<u8>>) {
+        let entries = match fs::read_dir(cwd.join(OsStr::from_bytes(path))) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name();
+            if name.as_bytes().starts_with(b".") && !self.hidden {
+                continue;
+            }
+            let child = join(path, name.as_bytes());
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                paths.push(child.clone());
+                self.walk(cwd, &child, files, paths);
+            } else if files {
+                paths.push(child);
+            }
+        }
+    }
+
+    fn read_dir(&self, cwd: &Path, path: &[u8], hidden: bool) -> Vec<Vec<u8>> {
+        let entries = match fs::read_dir(cwd.join(OsStr::from_bytes(path))) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().into_vec())
+            .filter(|name| hidden || self.hidden || !name.starts_with(b"."))
+            .collect()
+    }
+}
+
+/// Whether `text` matches the whole of `pattern`.
+pub fn matches(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where to resume after the most recent `*` if the rest fails to match.
+    let mut star = None;
+
+    while t < text.len() {
+        let advance = match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match match_bracket(&pattern[p..], text[t]) {
+                Some((true, len)) => Some(len),
+                Some((false, _)) => None,
+                None if text[t] == b'[' => Some(1),
+                None => None,
+            },
+            Some(b'\\') if p + 1 < pattern.len() => {
+                if pattern[p + 1] == text[t] {
+                    Some(2)
+                } else {
+                    None
+                }
+            }
+            Some(&byte) if byte == text[t] => Some(1),
+            _ => None,
+        };
+
+        match (advance, star) {
+            (Some(len), _) => {
+                p += len;
+                t += 1;
+            }
+            (None, Some((star_p, star_t))) => {
+                // Let the `*` swallow one more byte and try again.
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, t));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Whether a word contains any unescaped pattern characters. A `[` only
+/// counts if it starts a complete bracket expression.
+pub fn is_pattern(pattern: &[u8]) -> bool {
+    let mut i = 0;
+    while i < pattern.len() {
+        match pattern[i] {
+            b'\\' => i += 1,
+            b'*' | b'?' => return true,
+            b'[' if match_bracket(&pattern[i..], 0).is_some() => return true,
+            _ => {}
+        }
+        i += 1;
+    }
+    false
+}
+
+/// Whether a byte has a special meaning in a pattern.
+pub fn is_special(byte: u8) -> bool {
+    byte == b'*' || byte == b'?' || byte == b'[' || byte == b']' || byte == b'\\'
+}
+
+fn unescape(pattern: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(pattern.len());
+    let mut bytes = pattern.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\\' {
+            if let Some(&escaped) = bytes.next() {
+                buf.push(escaped);
+                continue;
+            }
+        }
+        buf.push(byte);
+    }
+    buf
+}
+
+fn join(path: &[u8], name: &[u8]) -> Vec<u8> {
+    let mut joined = path.to_vec();
+    if !joined.is_empty() && !joined.ends_with(b"/") {
+        joined.push(b'/');
+    }
+    joined.extend_from_slice(name);
+    joined
+}
+
+/// Matches a byte against a bracket expression like `[a-z]` or `[!0-9]` at
+/// the start of `pattern`, returning whether it matched and the length of
+/// the expression. Returns `None` if the bracket is never closed.
+fn match_bracket(pattern: &[u8], byte: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = match pattern.get(i) {
+        Some(b'!') | Some(b'^') => {
+            i += 1;
+            true
+        }
+        _ => false,
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(i)?;
+        if low == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if low == b'\\' {
+            i += 1;
+            low = *pattern.get(i)?;
+        }
+        i += 1;
+
+        let mut high = low;
+        if pattern.get(i) == Some(&b'-') && pattern.get(i + 1).is_some_and(|&b| b != b']') {
+            high = pattern[i + 1];
+            if high == b'\\' {
+                high = *pattern.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+
+        if low <= byte && byte <= high {
+            matched = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+    use std::process;
+
+    #[test]
+    fn wildcards() {
+        assert!(matches(b"*.rs", b"main.rs"));
+        assert!(matches(b"*.rs", b".rs"));
+        assert!(!matches(b"*.rs", b"main.rsx"));
+        assert!(matches(b"m??n.*", b"main.rs"));
+        assert!(!matches(b"m?n.rs", b"main.rs"));
+        assert!(matches(b"*a*b*", b"xxaxxbxx"));
+        assert!(!matches(b"*a*b", b"xxaxxbxx"));
+        assert!(matches(b"**", b""));
+        assert!(matches(b"literal", b"literal"));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(matches(b"file[0-9]", b"file7"));
+        assert!(!matches(b"file[0-9]", b"filex"));
+        assert!(matches(b"file[!0-9]", b"filex"));
+        assert!(matches(b"file[^0-9]", b"filex"));
+        assert!(matches(b"[]]", b"]"));
+        assert!(matches(b"[a-]", b"-"));
+        assert!(matches(b"[ch]at", b"hat"));
+        assert!(matches(b"[unclosed", b"[unclosed"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(br"\*", b"*"));
+        assert!(!matches(br"\*", b"a"));
+        assert!(!is_pattern(br"\*.rs"));
+        assert!(is_pattern(br"\\*.rs"));
+        assert!(is_pattern(b"[ab].rs"));
+        assert!(!is_pattern(b"["));
+        assert!(!is_pattern(b"[]"));
+        assert_eq!(unescape(br"a\*b\\"), br"a*b\".to_vec());
+    }
+
+    #[test]
+    fn expansion() {
+        let dir = env::temp_dir().join(format!("msh-glob-{}", process::id()));
+        fs::create_dir_all(dir.join("src/parser/.cache")).unwrap();
+        let files = &[
+            "src/main.rs",
+            "src/lib.rs",
+            "src/.hidden.rs",
+            "src/parser/mod.rs",
+            "src/parser/.cache/old.rs",
+            "README.md",
+        ];
+        for path in files {
+            File::create(dir.join(path)).unwrap();
+        }
+
+        let expand = |glob: Glob, pattern: &str| -> Vec<String> {
+            glob.expand(&dir, pattern.as_bytes())
+                .into_iter()
+                .map(|path| path.into_string().unwrap())
+                .collect()
+        };
+        let glob = Glob::new();
+        assert_eq!(expand(glob, "src/*.rs"), vec!["src/lib.rs", "src/main.rs"]);
+        assert_eq!(expand(glob, "src/.*.rs"), vec!["src/.hidden.rs"]);
+        assert_eq!(expand(glob, "*/"), vec!["src/"]);
+        assert_eq!(expand(glob, "s*/p*"), vec!["src/parser"]);
+        assert_eq!(expand(glob, "*.txt"), Vec::<String>::new());
+        assert_eq!(
+            expand(glob, &format!("{}/R*", dir.display())),
+            vec![format!("{}/README.md", dir.display())],
+        );
+
+        assert_eq!(
+            expand(glob, "src/**/*.rs"),
+            vec!["src/lib.rs", "src/main.rs", "src/parser/mod.rs"],
+        );
+        assert_eq!(
+            expand(glob, "**"),
+            vec![
+                "README.md",
+                "src",
+                "src/lib.rs",
+                "src/main.rs",
+                "src/parser",
+                "src/parser/mod.rs"
+            ],
+        );
+        assert_eq!(expand(glob, "**/"), vec!["src/", "src/parser/"],);
+
+        let glob = Glob::new().hidden(true);
+        assert_eq!(
+            expand(glob, "src/*.rs"),
+            vec!["src/.hidden.rs", "src/lib.rs", "src/main.rs"],
+        );
+        assert_eq!(
+            expand(glob, "src/**/old.rs"),
+            vec!["src/parser/.cache/old.rs"],
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
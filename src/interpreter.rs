@@ -1,33 +1,61 @@
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env;
-use std::ffi::CString;
+use std::ffi::{CString, OsStr, OsString};
 use std::fs::File;
+use std::io::{self, Read, Write};
 use std::mem;
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
-use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::Path;
 use std::process;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use failure::ResultExt;
-use libc;
 use nix::errno::Errno;
+use nix::fcntl::{self, FcntlArg};
 use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal};
-use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+use nix::sys::wait::{self, WaitStatus};
 use nix::unistd::{self, ForkResult, Pid};
 use nix::Error::Sys;
 
-use crate::ast::Stmt;
+use crate::ast::{self, AndOrList, Block, IfStmt, LogicalOp, MatchStmt, Stmt, WhileStmt};
 use crate::command::{Command, Execv, ExpandedCommand};
 use crate::cwd::Cwd;
 use crate::environment::Environment;
+use crate::glob;
+use crate::job::{Job, Jobs, State};
+use crate::options::Options;
 use crate::redirect::Redirect;
 use crate::status::Status;
+use crate::word::{self, Word};
 use crate::{print_error, Result};
 
 extern "C" fn nothing(_: libc::c_int) {}
 
+/// Whether SIGINT arrived while the `read` builtin was waiting for a line.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
 pub struct Interpreter {
     cwd: Cwd,
     env: Environment,
+    options: Options,
+    functions: HashMap<OsString, Rc<Block>>,
+    status: Status,
+    /// Our ends of the pipes of running process substitutions, along with
+    /// their processes.
+    substitutions: Vec<(RawFd, Pid)>,
+    /// Jobs that are stopped or running in the background.
+    jobs: Jobs,
+    /// Whether each pipeline runs in its own process group, with the
+    /// foreground one in control of the terminal.
+    job_control: bool,
+    /// The process most recently started with `&`, for `$!`.
+    last_background: Option<Pid>,
 }
 
 impl Interpreter {
@@ -46,57 +74,742 @@ impl Interpreter {
         Ok(Self {
             cwd: Cwd::new(),
             env: Environment::new(),
+            options: Options::new(),
+            functions: HashMap::new(),
+            status: Status::Success,
+            substitutions: Vec::new(),
+            jobs: Jobs::new(),
+            job_control: false,
+            last_background: None,
         })
     }
 
-    pub fn execute(&mut self, block: &[Stmt]) -> Result<()> {
+    /// Takes control of the terminal so that each pipeline can run in its
+    /// own process group, which lets Ctrl-Z stop it and `fg` and `bg` resume
+    /// it later.
+    pub fn enable_job_control(&mut self) -> Result<()> {
+        // Wait until we're in the foreground, like any other job.
+        loop {
+            let pgrp = unistd::getpgrp();
+            if unistd::tcgetpgrp(libc::STDIN_FILENO)? == pgrp {
+                break;
+            }
+            signal::kill(Pid::from_raw(-pgrp.as_raw()), Signal::SIGTTIN)?;
+        }
+
+        set_job_control_signals(SigHandler::SigIgn)?;
+        let pid = unistd::getpid();
+        if unistd::getpgrp() != pid {
+            unistd::setpgid(pid, pid)?;
+        }
+        unistd::tcsetpgrp(libc::STDIN_FILENO, pid)?;
+        self.job_control = true;
+        Ok(())
+    }
+
+    /// Reaps finished jobs, reporting any that finished or stopped since the
+    /// last time when job control is enabled.
+    pub fn update_jobs(&mut self) {
+        self.jobs.update(self.job_control);
+    }
+
+    pub fn execute(&mut self, program: &[Stmt]) -> Result<()> {
+        match self.execute_block(program)? {
+            Flow::Normal => Ok(()),
+            flow => Err(flow.misplaced()),
+        }
+    }
+
+    fn execute_block(&mut self, block: &[Stmt]) -> Result<Flow> {
         for stmt in block {
-            match *stmt {
-                Stmt::If(ref stmt) => {
-                    if self.execute_command(&stmt.test)?.is_success() {
-                        self.execute(&stmt.consequent)?;
-                    } else if let Some(ref alternate) = stmt.alternate {
-                        self.execute(alternate)?;
-                    }
+            // Process substitutions only live as long as their statement.
+            let mark = self.substitutions.len();
+            let flow = self.execute_stmt(stmt);
+            self.finish_substitutions(mark);
+            match flow? {
+                Flow::Normal => {}
+                flow => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Normal)
+    }
+
+    fn execute_stmt(&mut self, stmt: &Stmt) -> Result<Flow> {
+        match *stmt {
+            Stmt::If(ref stmt) => {
+                return self.with_redirects(&stmt.redirects, |shell| shell.execute_if(stmt));
+            }
+            Stmt::While(ref stmt) => {
+                return self
+                    .with_redirects(&stmt.redirects, |shell| shell.execute_while(stmt, false));
+            }
+            Stmt::Until(ref stmt) => {
+                return self
+                    .with_redirects(&stmt.redirects, |shell| shell.execute_while(stmt, true));
+            }
+            Stmt::For(ref stmt) => {
+                let mut words = Vec::new();
+                for word in &stmt.words {
+                    let fields = word.expand_fields(self)?;
+                    words.extend(fields.into_iter().map(Cow::into_owned));
                 }
-                Stmt::While(ref stmt) => {
-                    while self.execute_command(&stmt.test)?.is_success() {
-                        self.execute(&stmt.body)?;
+                for word in words {
+                    self.env.set(stmt.name.to_os_string(), word);
+                    match self.execute_block(&stmt.body)? {
+                        Flow::Normal | Flow::Continue => {}
+                        Flow::Break => break,
+                        flow @ Flow::Return(_) => return Ok(flow),
                     }
                 }
-                Stmt::Export(ref exportables) => {
-                    for exportable in exportables {
-                        self.env.export(exportable)?;
-                    }
+            }
+            Stmt::Match(ref stmt) => return self.execute_match(stmt),
+            Stmt::Function(ref function) => {
+                self.functions
+                    .insert(function.name.to_os_string(), Rc::clone(&function.body));
+            }
+            Stmt::Break => return Ok(Flow::Break),
+            Stmt::Continue => return Ok(Flow::Continue),
+            Stmt::Return(ref code) => {
+                if let Some(ref code) = *code {
+                    let code = code.expand(self)?;
+                    self.status = match code.to_str().and_then(|s| s.parse::<i32>().ok()) {
+                        Some(code) => code.into(),
+                        None => {
+                            display!("return: numeric argument required");
+                            Status::Failure
+                        }
+                    };
                 }
-                Stmt::Assignment(ref pairs) => {
-                    for pair in pairs {
-                        self.env.assign(pair)?;
-                    }
+                return Ok(Flow::Return(self.status));
+            }
+            Stmt::Export(ref exportables) => {
+                for exportable in exportables {
+                    let value = match exportable.value {
+                        Some(ref value) => Some(value.expand(self)?.into_owned()),
+                        None => None,
+                    };
+                    self.env.export(exportable.name.to_os_string(), value);
                 }
-                Stmt::Command(ref command) => {
-                    self.execute_command(command)?;
+            }
+            Stmt::Assignment(ref pairs) => {
+                for pair in pairs {
+                    let value = pair.value.expand(self)?.into_owned();
+                    self.env.set(pair.name.to_os_string(), value);
                 }
             }
+            Stmt::Command(ref command) => {
+                self.execute_command(command)?;
+            }
+            Stmt::AndOr(ref list) => return self.execute_and_or(list),
+            Stmt::Background(ref list) => self.execute_background(list)?,
+            Stmt::Group(ref group) => {
+                return self
+                    .with_redirects(&group.redirects, |shell| shell.execute_block(&group.body));
+            }
+            Stmt::Pipeline(ref stmts) => return self.execute_pipeline(stmts),
+            Stmt::Subshell(ref group) => {
+                self.status = self.with_redirects(&group.redirects, |shell| {
+                    shell.execute_subshell(&group.body)
+                })?;
+            }
         }
+        Ok(Flow::Normal)
+    }
 
-        Ok(())
+    fn execute_if(&mut self, stmt: &IfStmt) -> Result<Flow> {
+        match self.execute_and_or(&stmt.test)? {
+            Flow::Normal => {}
+            flow => return Ok(flow),
+        }
+        if self.status.is_success() {
+            self.execute_block(&stmt.consequent)
+        } else if let Some(ref alternate) = stmt.alternate {
+            self.execute_block(alternate)
+        } else {
+            self.status = Status::Success;
+            Ok(Flow::Normal)
+        }
+    }
+
+    /// Runs a `while` loop, or an `until` loop when the test is inverted. Like
+    /// a block, a loop has the status of the last command in its body.
+    fn execute_while(&mut self, stmt: &WhileStmt, until: bool) -> Result<Flow> {
+        let mut status = Status::Success;
+        loop {
+            let flow = match self.execute_and_or(&stmt.test)? {
+                Flow::Normal if self.status.is_success() == until => {
+                    self.status = status;
+                    break;
+                }
+                Flow::Normal => self.execute_block(&stmt.body)?,
+                flow => flow,
+            };
+            status = self.status;
+            match flow {
+                Flow::Normal | Flow::Continue => {}
+                Flow::Break => break,
+                flow @ Flow::Return(_) => return Ok(flow),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn execute_match(&mut self, stmt: &MatchStmt) -> Result<Flow> {
+        let word = stmt.word.expand(self)?.into_owned();
+        for arm in &stmt.arms {
+            for pattern in &arm.patterns {
+                if glob::matches(&pattern.expand_pattern(self)?, word.as_bytes()) {
+                    return self.execute_block(&arm.body);
+                }
+            }
+        }
+        Ok(Flow::Normal)
     }
 
     pub fn cwd(&self) -> String {
         self.cwd.current().display().to_string()
     }
 
-    fn execute_command(&mut self, command: &Command) -> Result<Status> {
-        let command = command.expand(&self.env)?;
+    pub fn current_dir(&self) -> &Path {
+        self.cwd.current()
+    }
 
-        match command.name().as_bytes() {
-            b"cd" => {
-                if command.pipeline().is_some() {
-                    unimplemented!("builtin pipelines");
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    pub fn env_mut(&mut self) -> &mut Environment {
+        &mut self.env
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn last_background(&self) -> Option<Pid> {
+        self.last_background
+    }
+
+    #[cfg(test)]
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    /// Runs a program in a subshell and returns what it wrote to stdout.
+    pub fn substitute(&mut self, program: &[Stmt]) -> Result<Vec<u8>> {
+        let (read, write) = unistd::pipe()?;
+
+        // As with a foreground job, Ctrl-C and Ctrl-\ are for the program and
+        // not the shell waiting for it.
+        let saved = ignore_interrupts()?;
+        let result = self
+            .fork(ProcessGroup::Shell, None, |shell| {
+                restore_actions(&saved)?;
+                unistd::close(read)?;
+                unistd::dup2(write, libc::STDOUT_FILENO)?;
+                unistd::close(write)?;
+                shell.execute(program)?;
+                Ok(shell.status)
+            })
+            .and_then(|child| {
+                unistd::close(write)?;
+                let mut output = Vec::new();
+                let result = unsafe { File::from_raw_fd(read) }.read_to_end(&mut output);
+                wait_for(child)?;
+                result?;
+                Ok(output)
+            });
+        restore_actions(&saved)?;
+        result
+    }
+
+    /// Starts a program for a `<(...)` or `>(...)` process substitution and
+    /// returns the `/dev/fd` path of our end of its pipe. With `read`, the
+    /// command reads the program's output from the path, otherwise it writes
+    /// the program's input to it.
+    pub fn substitute_process(&mut self, program: &[Stmt], read: bool) -> Result<OsString> {
+        let (read_end, write_end) = unistd::pipe()?;
+        let (ours, theirs, stdio) = if read {
+            (read_end, write_end, libc::STDOUT_FILENO)
+        } else {
+            (write_end, read_end, libc::STDIN_FILENO)
+        };
+
+        let child = self.fork(ProcessGroup::Shell, None, |shell| {
+            // Don't hold other substitutions open, or they'd never finish.
+            for (fd, _) in shell.substitutions.drain(..) {
+                let _ = unistd::close(fd);
+            }
+            unistd::close(ours)?;
+            unistd::dup2(theirs, stdio)?;
+            unistd::close(theirs)?;
+            shell.execute(program)?;
+            Ok(shell.status)
+        })?;
+
+        unistd::close(theirs)?;
+        self.substitutions.push((ours, child));
+        Ok(format!("/dev/fd/{}", ours).into())
+    }
+
+    /// Closes the pipes of the process substitutions started since `mark`
+    /// and waits for their processes to exit.
+    fn finish_substitutions(&mut self, mark: usize) {
+        if self.substitutions.len() <= mark {
+            return;
+        }
+        let substitutions: Vec<_> = self.substitutions.drain(mark..).collect();
+        for &(fd, _) in &substitutions {
+            let _ = unistd::close(fd);
+        }
+        for (_, pid) in substitutions {
+            if let Err(e) = wait_for(pid) {
+                debug!("wait: {}", e);
+            }
+        }
+    }
+
+    /// Starts a command or list without waiting for it. A lone external
+    /// command or pipeline is spawned directly, and anything that needs the
+    /// shell itself runs in a subshell.
+    fn execute_background(&mut self, list: &AndOrList) -> Result<()> {
+        if !self.job_control {
+            // Nobody is told about finished jobs, so reap them as we go and
+            // keep their statuses for `wait`.
+            self.jobs.update(false);
+        }
+
+        let group = if self.job_control {
+            ProcessGroup::Background
+        } else {
+            ProcessGroup::Shell
+        };
+        let pids = match *list.first {
+            Stmt::Command(ref command) if list.rest.is_empty() && !command.is_negated() => {
+                let expanded = command.expand(self)?;
+                self.spawn_children(&expanded, group)
+            }
+            _ => vec![self.fork(group, None, |shell| {
+                let flow = shell.execute_and_or(list)?;
+                shell.exit_status(flow)
+            })?],
+        };
+
+        let job = Job::new(pids, list.to_string());
+        let pid = job.last_pid();
+        self.last_background = Some(pid);
+        let id = self.jobs.add(job);
+        if self.job_control {
+            println!("[{}] {}", id, pid);
+        }
+        self.status = Status::Success;
+        Ok(())
+    }
+
+    /// Runs `f` in a forked copy of the shell, exiting with its status. The
+    /// child joins the group led by `leader`, or leads a new one.
+    fn fork<F>(&mut self, group: ProcessGroup, leader: Option<Pid>, f: F) -> Result<Pid>
+    where
+        F: FnOnce(&mut Self) -> Result<Status>,
+    {
+        match unistd::fork()? {
+            ForkResult::Parent { child } => {
+                group.join(child, leader);
+                Ok(child)
+            }
+            ForkResult::Child => {
+                group.join(unistd::getpid(), leader);
+                self.enter_subshell();
+                let code = match f(self) {
+                    Ok(status) => status.code(),
+                    Err(e) => {
+                        print_error(&e);
+                        libc::EXIT_FAILURE
+                    }
+                };
+                process::exit(code);
+            }
+        }
+    }
+
+    /// Sets up a forked child to run commands on its own. Its parent's jobs
+    /// aren't its children, and its commands stay in its process group.
+    fn enter_subshell(&mut self) {
+        // The shell may have forked while waiting for signals, and the child
+        // has to die of them like any other process.
+        if let Err(e) = signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&wait_sigset()), None) {
+            debug!("failed unblocking signals: {}", e);
+        }
+        // Like any other process, stop once nobody reads what we write.
+        if let Err(e) = set_signal_handler(Signal::SIGPIPE, SigHandler::SigDfl) {
+            debug!("failed restoring SIGPIPE: {}", e);
+        }
+        self.jobs.clear();
+        if self.job_control {
+            self.job_control = false;
+            if let Err(e) = set_job_control_signals(SigHandler::SigDfl) {
+                debug!("failed restoring signals: {}", e);
+            }
+        }
+    }
+
+    /// Runs a block in a forked copy of the shell and waits for it.
+    fn execute_subshell(&mut self, body: &[Stmt]) -> Result<Status> {
+        let sigset = wait_sigset();
+        let mask = block_signals(&sigset);
+
+        let group = if self.job_control {
+            ProcessGroup::Foreground
+        } else {
+            ProcessGroup::Shell
+        };
+        let result = self.fork(group, None, |shell| {
+            let flow = shell.execute_block(body)?;
+            shell.exit_status(flow)
+        });
+        let status =
+            result.map(|pid| self.wait_for_job(Job::new(vec![pid], "( ... )".into()), &sigset));
+
+        restore_mask(&mask);
+
+        status
+    }
+
+    /// Runs a pipeline of statements as a job. All but the last run in forked
+    /// copies of the shell, and without job control the last runs in the shell
+    /// itself reading from the pipe, so that variables it sets are still there
+    /// afterwards. With job control it's forked too, like in bash, so that
+    /// Ctrl-Z can stop the whole job rather than leave the shell reading from
+    /// a stopped one.
+    fn execute_pipeline(&mut self, stmts: &[Stmt]) -> Result<Flow> {
+        let (last, rest) = stmts.split_last().expect("empty pipeline");
+        let (group, forked) = if self.job_control {
+            (ProcessGroup::Foreground, stmts)
+        } else {
+            (ProcessGroup::Shell, rest)
+        };
+
+        let sigset = wait_sigset();
+        let mask = block_signals(&sigset);
+
+        let mut pids = Vec::new();
+        let mut stdin = None;
+        let mut result = Ok(Flow::Normal);
+        for (i, stmt) in forked.iter().enumerate() {
+            let pipe = if i + 1 < stmts.len() {
+                Some(unistd::pipe().expect("failed creating pipe"))
+            } else {
+                None
+            };
+            let leader = pids.first().cloned();
+            let pid = self.fork(group, leader, |shell| {
+                let stdout = match pipe {
+                    Some((read, write)) => {
+                        unistd::close(read)?;
+                        Some(write)
+                    }
+                    None => None,
+                };
+                redirect_stdio(stdin, stdout)?;
+                let flow = shell.execute_stmt(stmt)?;
+                shell.exit_status(flow)
+            });
+            if let Some((_, write)) = pipe {
+                unistd::close(write).expect("failed closing write end of pipe");
+            }
+            if let Some(fd) = mem::replace(&mut stdin, pipe.map(|(read, _)| read)) {
+                unistd::close(fd).expect("failed closing read end of pipe");
+            }
+            match pid {
+                Ok(pid) => pids.push(pid),
+                Err(e) => {
+                    result = Err(e);
+                    break;
                 }
-                Ok(self.cwd.cd(self.env.home(), command.arguments()))
             }
+        }
+
+        if let Some(read) = stdin {
+            if result.is_ok() {
+                let saved = save_fd(libc::STDIN_FILENO);
+                result = redirect_stdio(Some(read), None).and_then(|()| self.execute_stmt(last));
+                restore_fd(libc::STDIN_FILENO, saved);
+            } else {
+                let _ = unistd::close(read);
+            }
+        }
+
+        if !pids.is_empty() {
+            let job = Job::new(pids, ast::describe_pipeline(stmts));
+            let status = self.wait_for_job(job, &sigset);
+            if self.job_control {
+                self.status = status;
+            }
+        }
+
+        restore_mask(&mask);
+        result
+    }
+
+    /// The status that a forked copy of the shell exits with once it's done.
+    fn exit_status(&self, flow: Flow) -> Result<Status> {
+        match flow {
+            Flow::Normal => Ok(self.status),
+            Flow::Return(status) => Ok(status),
+            flow => Err(flow.misplaced()),
+        }
+    }
+
+    /// Applies redirects to the shell's own file descriptors while `f` runs,
+    /// so that builtins and children inherit them, and then restores them.
+    fn with_redirects<T, F>(&mut self, redirects: &[Redirect<Word>], f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        if redirects.is_empty() {
+            return f(self);
+        }
+
+        let mut expanded = Vec::new();
+        for redirect in redirects {
+            expanded.push(redirect.try_map(|word| word.expand(self))?);
+        }
+        self.with_expanded_redirects(&expanded, f)
+    }
+
+    fn with_expanded_redirects<P, T, F>(&mut self, redirects: &[Redirect<P>], f: F) -> Result<T>
+    where
+        P: AsRef<OsStr>,
+        F: FnOnce(&mut Self) -> Result<T>,
+    {
+        let mut saved = Vec::new();
+        let mut result = Ok(());
+        for redirect in redirects {
+            saved.push((redirect.fd, save_fd(redirect.fd)));
+            result = redirect.apply(self.options.noclobber);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let result = result.and_then(|()| f(self));
+
+        for (fd, copy) in saved.into_iter().rev() {
+            restore_fd(fd, copy);
+        }
+        result
+    }
+
+    /// Runs an external command or pipeline in the foreground.
+    fn execute_job(&mut self, command: &Command, expanded: &ExpandedCommand) -> Status {
+        // Block before forking so a child that exits immediately can't deliver
+        // SIGCHLD before we start waiting for it.
+        let sigset = wait_sigset();
+        let mask = block_signals(&sigset);
+
+        let group = if self.job_control {
+            ProcessGroup::Foreground
+        } else {
+            ProcessGroup::Shell
+        };
+        let pids = self.spawn_children(expanded, group);
+        let status = self.wait_for_job(Job::new(pids, command.to_string()), &sigset);
+
+        restore_mask(&mask);
+
+        status
+    }
+
+    /// Waits for a foreground job to finish or stop, with `sigset` already
+    /// blocked, then takes back the terminal. A stopped job joins the job
+    /// table.
+    fn wait_for_job(&mut self, mut job: Job, sigset: &SigSet) -> Status {
+        // Without job control there's nothing to do with a stopped job but
+        // wait for it to be continued.
+        let job_control = self.job_control;
+        let waiting = |state| match state {
+            State::Running => true,
+            State::Stopped => !job_control,
+            State::Done(_) => false,
+        };
+
+        job.update();
+        while waiting(job.state()) {
+            let signal = sigset.wait().expect("failed waiting for signal");
+            match signal {
+                Signal::SIGINT | Signal::SIGQUIT => debug!("ignoring {:?}", signal),
+                Signal::SIGCHLD => job.update(),
+                signal => panic!("received unexpected {:?}", signal),
+            }
+        }
+
+        if self.job_control {
+            if let Err(e) = unistd::tcsetpgrp(libc::STDIN_FILENO, unistd::getpgrp()) {
+                debug!("failed taking back the terminal: {}", e);
+            }
+        }
+
+        match job.state() {
+            State::Done(status) => status,
+            _ => {
+                self.jobs.add(job);
+                let index = self.jobs.find(None).unwrap();
+                println!("\n{}", self.jobs.describe(index));
+                Status::Failure
+            }
+        }
+    }
+
+    /// Finds the job for the argument of `fg` or `bg`, reporting any problem.
+    fn find_job(&self, builtin: &str, argv: &[Cow<OsStr>]) -> Option<usize> {
+        if !self.job_control {
+            display!("{}: no job control", builtin);
+            return None;
+        }
+        if argv.len() > 1 {
+            display!("{}: too many arguments", builtin);
+            return None;
+        }
+
+        let spec = argv.first().map(AsRef::as_ref);
+        let index = self.jobs.find(spec);
+        if index.is_none() {
+            match spec {
+                Some(spec) => display!("{}: {}: no such job", builtin, spec.to_string_lossy()),
+                None => display!("{}: no current job", builtin),
+            }
+        }
+        index
+    }
+
+    /// Continues a job in the foreground and waits for it.
+    fn foreground(&mut self, argv: &[Cow<OsStr>]) -> Status {
+        let index = match self.find_job("fg", argv) {
+            Some(index) => index,
+            None => return Status::Failure,
+        };
+        let mut job = self.jobs.remove(index);
+        println!("{}", job.text());
+
+        let sigset = wait_sigset();
+        let mask = block_signals(&sigset);
+
+        if let Err(e) = unistd::tcsetpgrp(libc::STDIN_FILENO, job.pgid()) {
+            debug!("failed handing over the terminal: {}", e);
+        }
+        if let Err(e) = job.resume() {
+            display!("fg: {}", e);
+        }
+        let status = self.wait_for_job(job, &sigset);
+
+        restore_mask(&mask);
+
+        status
+    }
+
+    /// Continues a stopped job in the background.
+    fn background(&mut self, argv: &[Cow<OsStr>]) -> Status {
+        let index = match self.find_job("bg", argv) {
+            Some(index) => index,
+            None => return Status::Failure,
+        };
+        let job = self.jobs.get_mut(index);
+        if let Err(e) = job.resume() {
+            display!("bg: {}", e);
+            return Status::Failure;
+        }
+        println!("[{}] {} &", job.id(), job.text());
+        Status::Success
+    }
+
+    /// Waits for the given jobs, by process ID or job spec, or all of them,
+    /// returning the status of the last one.
+    fn wait(&mut self, argv: &[Cow<OsStr>]) -> Status {
+        if argv.is_empty() {
+            for mut job in self.jobs.drain() {
+                job.wait();
+            }
+            return Status::Success;
+        }
+
+        let mut status = Status::Success;
+        for arg in argv {
+            let index = if arg.as_bytes().starts_with(b"%") {
+                self.jobs.find(Some(arg))
+            } else {
+                arg.to_str()
+                    .and_then(|s| s.parse().ok())
+                    .and_then(|pid| self.jobs.find_pid(Pid::from_raw(pid)))
+            };
+            status = match index {
+                Some(index) => self.jobs.remove(index).wait(),
+                None => {
+                    display!("wait: {}: no such job", arg.to_string_lossy());
+                    Status::Failure
+                }
+            };
+        }
+        status
+    }
+
+    /// Runs an and-or list, leaving the status of the last statement that ran
+    /// in `self.status`. A `break` or `return` ends the list early.
+    fn execute_and_or(&mut self, list: &AndOrList) -> Result<Flow> {
+        let mut flow = self.execute_stmt(&list.first)?;
+        for &(op, ref stmt) in &list.rest {
+            if flow != Flow::Normal {
+                break;
+            }
+            let should_run = match op {
+                LogicalOp::And => self.status.is_success(),
+                LogicalOp::Or => !self.status.is_success(),
+            };
+            if should_run {
+                flow = self.execute_stmt(stmt)?;
+            }
+        }
+        Ok(flow)
+    }
+
+    fn execute_command(&mut self, command: &Command) -> Result<Status> {
+        let mark = self.substitutions.len();
+        let result = command
+            .expand(self)
+            .and_then(|expanded| self.execute_expanded(command, &expanded));
+        self.finish_substitutions(mark);
+
+        let mut status = result?;
+        if command.is_negated() {
+            status = !status;
+        }
+        self.status = status;
+        Ok(status)
+    }
+
+    fn execute_expanded(&mut self, source: &Command, command: &ExpandedCommand) -> Result<Status> {
+        if command.pipeline().is_none() && self.is_builtin(command) {
+            self.with_expanded_redirects(command.redirects(), |shell| {
+                shell.execute_builtin(command)
+            })
+        } else {
+            Ok(self.execute_job(source, command))
+        }
+    }
+
+    /// Runs a builtin or function, ignoring any pipeline after it.
+    fn execute_builtin(&mut self, command: &ExpandedCommand) -> Result<Status> {
+        Ok(match command.name().as_bytes() {
+            b"cd" => self.cwd.cd(self.env.home(), command.arguments()),
+            b"set" => self.options.set(command.arguments()),
+            b"read" => self.read(command.arguments()),
+            b"wait" => self.wait(command.arguments()),
+            b"jobs" => {
+                self.jobs.print();
+                Status::Success
+            }
+            b"fg" => self.foreground(command.arguments()),
+            b"bg" => self.background(command.arguments()),
             b"exit" => {
                 if command.arguments().len() > 1 {
                     display!("exit: too many arguments");
@@ -115,138 +828,407 @@ impl Interpreter {
                 };
                 process::exit(code);
             }
-            _ => Ok(execute(&command, &self.env)),
+            _ => {
+                let body = Rc::clone(&self.functions[command.name()]);
+                return self.call_function(command, &body);
+            }
+        })
+    }
+
+    /// Reads a line from stdin and splits it into fields on blanks, setting
+    /// each variable named in `argv` to the next field and the last one to
+    /// the rest of the line. Without `-r`, a backslash escapes the byte
+    /// after it and continues the line before a newline.
+    fn read(&mut self, argv: &[Cow<OsStr>]) -> Status {
+        let (raw, names) = match argv.first() {
+            Some(flag) if flag.as_bytes() == b"-r" => (true, &argv[1..]),
+            _ => (false, argv),
+        };
+        for name in names {
+            if !word::is_valid_name(name.as_bytes()) {
+                display!("read: invalid variable name: {}", name.to_string_lossy());
+                return Status::Failure;
+            }
+        }
+
+        // Ctrl-C gives up on the line rather than killing the shell.
+        INTERRUPTED.store(false, Ordering::SeqCst);
+        let action = SigAction::new(
+            SigHandler::Handler(interrupt),
+            SaFlags::empty(),
+            SigSet::empty(),
+        );
+        let saved = unsafe { signal::sigaction(Signal::SIGINT, &action) };
+        let result = read_line(raw);
+        if let Ok(saved) = saved {
+            unsafe {
+                let _ = signal::sigaction(Signal::SIGINT, &saved);
+            }
+        }
+        let (line, eof) = match result {
+            Ok(line) => line,
+            Err(Sys(Errno::EINTR)) => return Status::Failure,
+            Err(e) => {
+                display!("read: {}", e);
+                return Status::Failure;
+            }
+        };
+
+        let default = [Cow::Borrowed(OsStr::new("REPLY"))];
+        let names = if names.is_empty() { &default } else { names };
+        for (name, value) in names.iter().zip(split_fields(&line, names.len())) {
+            self.env
+                .set(name.clone().into_owned(), OsString::from_vec(value));
+        }
+
+        if eof {
+            Status::Failure
+        } else {
+            Status::Success
+        }
+    }
+
+    /// Whether a command runs inside the shell rather than as a new process.
+    fn is_builtin(&self, command: &ExpandedCommand) -> bool {
+        match command.name().as_bytes() {
+            b"cd" | b"set" | b"read" | b"wait" | b"jobs" | b"fg" | b"bg" | b"exit" => true,
+            _ => self.functions.contains_key(command.name()),
+        }
+    }
+
+    /// Starts every command in a pipeline, returning their process IDs in
+    /// order. Builtins and functions run in a forked copy of the shell.
+    fn spawn_children(&mut self, cmd: &ExpandedCommand, group: ProcessGroup) -> Vec<Pid> {
+        let mut pids = Vec::new();
+        let mut next_cmd = Some(cmd);
+        let mut next_stdin = None;
+
+        while let Some(cmd) = next_cmd {
+            let (stdin, stdout) = match cmd.pipeline() {
+                Some(next) => {
+                    next_cmd = Some(next);
+                    let (read, write) = unistd::pipe().expect("failed creating pipe");
+                    (next_stdin.replace(read), Some(write))
+                }
+                None => {
+                    next_cmd = None;
+                    (next_stdin.take(), None)
+                }
+            };
+
+            match unistd::fork().expect("failed to fork") {
+                ForkResult::Parent { child } => {
+                    group.join(child, pids.first().cloned());
+                    pids.push(child);
+                    if let Some(fd) = stdin {
+                        unistd::close(fd).expect("failed closing read end of pipe");
+                    }
+                    if let Some(fd) = stdout {
+                        unistd::close(fd).expect("failed closing write end of pipe");
+                    }
+                    if next_cmd.is_none() {
+                        return pids;
+                    }
+                }
+                ForkResult::Child => {
+                    group.join(unistd::getpid(), pids.first().cloned());
+                    if group != ProcessGroup::Shell {
+                        if let Err(e) = set_job_control_signals(SigHandler::SigDfl) {
+                            debug!("failed restoring signals: {}", e);
+                        }
+                    }
+                    // The next command reads this end, and if we held it
+                    // open too we'd never see it go away.
+                    if let Some(fd) = next_stdin {
+                        let _ = unistd::close(fd);
+                    }
+                    let result = redirect_stdio(stdin, stdout).and_then(|()| {
+                        if self.is_builtin(cmd) {
+                            self.execute_builtin_child(cmd)
+                        } else {
+                            execute_child(cmd, &self.env, &self.options)
+                        }
+                    });
+                    let code = match result {
+                        Ok(status) => status.code(),
+                        Err(e) => {
+                            print_error(&e);
+                            libc::EXIT_FAILURE
+                        }
+                    };
+                    process::exit(code);
+                }
+            }
+        }
+        unreachable!();
+    }
+
+    /// Runs a builtin or function in a forked child that's part of a
+    /// pipeline.
+    fn execute_builtin_child(&mut self, cmd: &ExpandedCommand) -> Result<Status> {
+        self.enter_subshell();
+        for redirect in cmd.redirects() {
+            redirect.apply(self.options.noclobber)?;
+        }
+        self.execute_builtin(cmd)
+    }
+
+    fn call_function(&mut self, command: &ExpandedCommand, body: &[Stmt]) -> Result<Status> {
+        let arguments = command
+            .arguments()
+            .iter()
+            .map(|argument| argument.clone().into_owned())
+            .collect();
+
+        let saved = self.env.set_positional(arguments);
+        let result = self.execute_block(body);
+        self.env.set_positional(saved);
+
+        match result? {
+            Flow::Normal => Ok(self.status),
+            Flow::Return(status) => Ok(status),
+            flow => Err(flow.misplaced()),
+        }
+    }
+}
+
+/// How control leaves a block. Anything other than `Normal` unwinds the
+/// enclosing blocks until it reaches the loop or function that handles it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Status),
+}
+
+impl Flow {
+    fn misplaced(self) -> failure::Error {
+        match self {
+            Flow::Break => format_err!("break: only meaningful in a loop"),
+            Flow::Continue => format_err!("continue: only meaningful in a loop"),
+            Flow::Return(_) => format_err!("return: can only be used in a function"),
+            Flow::Normal => unreachable!(),
         }
     }
 }
 
-fn execute(cmd: &ExpandedCommand, env: &Environment) -> Status {
-    let (mut pids, last_pid) = spawn_children(cmd, env);
+/// Waits for a single child that the shell is running synchronously.
+fn wait_for(pid: Pid) -> Result<Status> {
+    loop {
+        match wait::waitpid(pid, None) {
+            Ok(WaitStatus::Exited(_, code)) => return Ok(code.into()),
+            Ok(WaitStatus::Signaled(..)) => return Ok(Status::Failure),
+            Ok(status) => debug!("wait: {:?}", status),
+            Err(Sys(Errno::EINTR)) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
 
+/// The signals the shell waits for synchronously while children run.
+fn wait_sigset() -> SigSet {
     let mut sigset = SigSet::empty();
     sigset.add(Signal::SIGINT);
     sigset.add(Signal::SIGQUIT);
     sigset.add(Signal::SIGCHLD);
+    sigset
+}
 
-    signal::sigprocmask(SigmaskHow::SIG_BLOCK, Some(&sigset), None)
+/// Blocks `sigset`, returning the mask to put back with `restore_mask`. Some
+/// of it may already be blocked, as when a builtin in a pipeline runs a job.
+fn block_signals(sigset: &SigSet) -> SigSet {
+    let mut mask = SigSet::empty();
+    signal::sigprocmask(SigmaskHow::SIG_BLOCK, Some(sigset), Some(&mut mask))
         .expect("failed blocking signals");
+    mask
+}
 
-    let mut status = Status::Success;
-    'outer: loop {
-        let signal = sigset.wait().expect("failed waiting for signal");
-        match signal {
-            Signal::SIGINT | Signal::SIGQUIT => debug!("ignoring {:?}", signal),
-            Signal::SIGCHLD => loop {
-                match wait::waitpid(None, Some(WaitPidFlag::WNOHANG)) {
-                    Ok(WaitStatus::Exited(pid, code)) => {
-                        debug!("PID {} returned {}", pid, code);
-                        assert!(pids.remove(&pid));
-                        if pid == last_pid {
-                            status = code.into();
-                        }
-                    }
-                    Ok(WaitStatus::Signaled(pid, signal, _)) => {
-                        debug!("PID {} received {:?}", pid, signal);
-                        assert!(pids.remove(&pid));
-                        if pid == last_pid {
-                            status = Status::Failure;
-                        }
-                    }
-                    Ok(WaitStatus::StillAlive) => break,
-                    Ok(status) => debug!("wait: {:?}", status),
-                    Err(Sys(Errno::ECHILD)) => break 'outer,
-                    Err(e) => panic!("wait: {}", e),
-                }
-            },
-            signal => panic!("received unexpected {:?}", signal),
+fn restore_mask(mask: &SigSet) {
+    signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(mask), None)
+        .expect("failed restoring signal mask");
+}
+
+/// Where the shell keeps copies of file descriptors that a redirect on a
+/// compound statement replaces.
+const SAVED_FD_MIN: RawFd = 10;
+
+/// Copies a file descriptor that's about to be replaced out of the way of
+/// children, returning `None` if it isn't open.
+fn save_fd(fd: RawFd) -> Option<RawFd> {
+    let _ = io::stdout().flush();
+    fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(SAVED_FD_MIN)).ok()
+}
+
+/// Puts back a file descriptor saved by `save_fd`.
+fn restore_fd(fd: RawFd, copy: Option<RawFd>) {
+    let _ = io::stdout().flush();
+    match copy {
+        Some(copy) => {
+            if let Err(e) = unistd::dup2(copy, fd) {
+                debug!("failed restoring fd {}: {}", fd, e);
+            }
+            let _ = unistd::close(copy);
+        }
+        None => {
+            let _ = unistd::close(fd);
         }
     }
+}
+
+/// The signals that stop a process for using the terminal from the
+/// background, or when the user presses Ctrl-Z.
+const JOB_CONTROL_SIGNALS: [Signal; 3] = [Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU];
+
+fn set_job_control_signals(handler: SigHandler) -> Result<()> {
+    for &signal in &JOB_CONTROL_SIGNALS {
+        set_signal_handler(signal, handler)?;
+    }
+    Ok(())
+}
+
+fn set_signal_handler(signal: Signal, handler: SigHandler) -> Result<()> {
+    let action = SigAction::new(handler, SaFlags::empty(), SigSet::empty());
+    unsafe {
+        signal::sigaction(signal, &action)?;
+    }
+    Ok(())
+}
 
-    assert!(pids.is_empty());
+/// The signals that the terminal sends for Ctrl-C and Ctrl-\.
+const INTERRUPT_SIGNALS: [Signal; 2] = [Signal::SIGINT, Signal::SIGQUIT];
 
-    signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&sigset), None)
-        .expect("failed unblocking signals");
+/// Ignores the interrupt signals, returning the actions to put back with
+/// `restore_actions`.
+fn ignore_interrupts() -> Result<Vec<(Signal, SigAction)>> {
+    let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+    let mut saved = Vec::new();
+    for &signal in &INTERRUPT_SIGNALS {
+        saved.push((signal, unsafe { signal::sigaction(signal, &ignore)? }));
+    }
+    Ok(saved)
+}
 
-    status
+fn restore_actions(saved: &[(Signal, SigAction)]) -> Result<()> {
+    for (signal, action) in saved {
+        unsafe {
+            signal::sigaction(*signal, action)?;
+        }
+    }
+    Ok(())
 }
 
-fn spawn_children(cmd: &ExpandedCommand, env: &Environment) -> (HashSet<Pid>, Pid) {
-    let mut pids = HashSet::new();
-    let mut next_cmd = Some(cmd);
-    let mut next_stdin = None;
+/// The process group that a job's processes run in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ProcessGroup {
+    /// The shell's own, when job control is disabled.
+    Shell,
+    /// A new group that's given the terminal.
+    Foreground,
+    /// A new group that's left in the background.
+    Background,
+}
 
-    while let Some(cmd) = next_cmd {
-        let (stdin, stdout) = match cmd.pipeline() {
-            Some(next) => {
-                next_cmd = Some(next);
-                let (read, write) = unistd::pipe().expect("failed creating pipe");
-                (mem::replace(&mut next_stdin, Some(read)), Some(write))
+impl ProcessGroup {
+    /// Moves `pid` into the group led by `leader`, or a new group of its own.
+    /// Both the shell and the child do this, so that the child is in place
+    /// whichever of them runs first.
+    fn join(self, pid: Pid, leader: Option<Pid>) {
+        if self == ProcessGroup::Shell {
+            return;
+        }
+        let pgid = leader.unwrap_or(pid);
+        if let Err(e) = unistd::setpgid(pid, pgid) {
+            debug!("setpgid: {}", e);
+        }
+        if self == ProcessGroup::Foreground {
+            if let Err(e) = unistd::tcsetpgrp(libc::STDIN_FILENO, pgid) {
+                debug!("tcsetpgrp: {}", e);
             }
-            None => {
-                next_cmd = None;
-                (next_stdin, None)
+        }
+    }
+}
+
+/// Reads a line for the `read` builtin, along with whether it ended at EOF
+/// rather than a newline. Each byte is marked with whether it was escaped.
+/// A SIGINT caught by `interrupt` fails it with `EINTR`.
+fn read_line(raw: bool) -> nix::Result<(Vec<(u8, bool)>, bool)> {
+    // Read a byte at a time so that nothing after the line is consumed.
+    let mut line = Vec::new();
+    let mut escaped = false;
+    loop {
+        if INTERRUPTED.swap(false, Ordering::SeqCst) {
+            return Err(Sys(Errno::EINTR));
+        }
+        let mut byte = [0];
+        match unistd::read(libc::STDIN_FILENO, &mut byte) {
+            Ok(0) => return Ok((line, true)),
+            Ok(_) => {}
+            // Other signals, like SIGCHLD from a background job, don't
+            // interrupt the line.
+            Err(Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(e),
+        }
+        match byte[0] {
+            b'\n' if escaped => escaped = false,
+            b'\n' => return Ok((line, false)),
+            b'\\' if !raw && !escaped => escaped = true,
+            byte => {
+                line.push((byte, escaped));
+                escaped = false;
             }
+        }
+    }
+}
+
+/// Splits a line of bytes, each marked with whether it was escaped, into at
+/// most `count` fields separated by unescaped blanks. The last field is the
+/// rest of the line. Missing fields are empty.
+fn split_fields(line: &[(u8, bool)], count: usize) -> Vec<Vec<u8>> {
+    let is_blank = |&(byte, escaped): &(u8, bool)| !escaped && (byte == b' ' || byte == b'\t');
+    let mut line = line;
+    while line.last().is_some_and(is_blank) {
+        line = &line[..line.len() - 1];
+    }
+
+    let mut fields = Vec::new();
+    for i in 0..count {
+        while line.first().is_some_and(is_blank) {
+            line = &line[1..];
+        }
+        let len = if i + 1 == count {
+            line.len()
+        } else {
+            line.iter().position(is_blank).unwrap_or(line.len())
         };
+        fields.push(line[..len].iter().map(|&(byte, _)| byte).collect());
+        line = &line[len..];
+    }
+    fields
+}
 
-        match unistd::fork().expect("failed to fork") {
-            ForkResult::Parent { child } => {
-                pids.insert(child);
-                if let Some(fd) = stdin {
-                    unistd::close(fd).expect("failed closing read end of pipe");
-                }
-                if let Some(fd) = stdout {
-                    unistd::close(fd).expect("failed closing write end of pipe");
-                }
-                if next_cmd.is_none() {
-                    return (pids, child);
-                }
-            }
-            ForkResult::Child => {
-                if let Err(e) = execute_child(cmd, env, stdin, stdout) {
-                    print_error(&e);
-                }
-                process::exit(1);
-            }
+/// Moves the ends of the pipes a child was given onto its stdin and stdout.
+fn redirect_stdio(stdin: Option<RawFd>, stdout: Option<RawFd>) -> Result<()> {
+    for (fd, stdio) in [(stdin, libc::STDIN_FILENO), (stdout, libc::STDOUT_FILENO)] {
+        if let Some(fd) = fd {
+            unistd::dup2(fd, stdio)?;
+            unistd::close(fd)?;
         }
     }
-    unreachable!();
+    Ok(())
 }
 
 fn execute_child(
     cmd: &ExpandedCommand,
     environment: &Environment,
-    stdin: Option<RawFd>,
-    stdout: Option<RawFd>,
-) -> Result<()> {
-    if let Some(fd) = stdin {
-        unistd::dup2(fd, libc::STDIN_FILENO)?;
-    }
-    if let Some(fd) = stdout {
-        unistd::dup2(fd, libc::STDOUT_FILENO)?;
-    }
+    options: &Options,
+) -> Result<Status> {
+    signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&wait_sigset()), None)?;
+    // Rust ignores SIGPIPE, but programs expect to be killed by it.
+    set_signal_handler(Signal::SIGPIPE, SigHandler::SigDfl)?;
 
     for redirect in cmd.redirects() {
-        match *redirect {
-            Redirect::InFile(ref path) => {
-                let file =
-                    File::open(path).with_context(|_| path.to_string_lossy().into_owned())?;
-                unistd::dup2(file.as_raw_fd(), libc::STDIN_FILENO)?;
-            }
-            Redirect::OutErr => {
-                unistd::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO)?;
-            }
-            Redirect::OutFile(ref path, mode) => {
-                let file = mode.open(path)?;
-                unistd::dup2(file.as_raw_fd(), libc::STDOUT_FILENO)?;
-            }
-            Redirect::ErrOut => {
-                unistd::dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO)?;
-            }
-            Redirect::ErrFile(ref path, mode) => {
-                let file = mode.open(path)?;
-                unistd::dup2(file.as_raw_fd(), libc::STDERR_FILENO)?;
-            }
-        }
+        redirect.apply(options.noclobber)?;
     }
 
     match cmd.clone().into_execv(environment) {
@@ -274,3 +1256,249 @@ fn execve(path: &CString, argv: &[CString], env: &[CString]) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use nix::pty;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Runs a program in a subshell and returns what it wrote to stdout.
+    fn run(src: &str) -> String {
+        let mut shell = Interpreter::new().unwrap();
+        let program = parser::parse(src.as_bytes()).unwrap();
+        String::from_utf8(shell.substitute(&program).unwrap()).unwrap()
+    }
+
+    /// Runs a program in a forked shell with job control on a terminal of its
+    /// own, typing `keys` into the terminal once the program has had time to
+    /// get going. Returns how the shell exited and how long it took.
+    fn run_in_terminal(src: &str, keys: &[u8]) -> (WaitStatus, Duration) {
+        let program = parser::parse(src.as_bytes()).unwrap();
+        let terminal =
+            pty::openpty(None::<&pty::Winsize>, None::<&nix::sys::termios::Termios>).unwrap();
+        let start = Instant::now();
+        match unistd::fork().unwrap() {
+            ForkResult::Parent { child } => {
+                unistd::close(terminal.slave).unwrap();
+                thread::sleep(Duration::from_millis(500));
+                unistd::write(terminal.master, keys).unwrap();
+                let status = wait::waitpid(child, None).unwrap();
+                unistd::close(terminal.master).unwrap();
+                (status, start.elapsed())
+            }
+            ForkResult::Child => {
+                // Don't panic here, or the test harness would carry on in
+                // the child too.
+                let code = (|| {
+                    unistd::setsid()?;
+                    if unsafe { libc::ioctl(terminal.slave, libc::TIOCSCTTY, 0) } == -1 {
+                        bail!("failed taking the terminal");
+                    }
+                    for fd in 0..3 {
+                        unistd::dup2(terminal.slave, fd)?;
+                    }
+                    let mut shell = Interpreter::new()?;
+                    shell.enable_job_control()?;
+                    shell.execute(&program)
+                })()
+                .map_or(99, |()| 0);
+                process::exit(code);
+            }
+        }
+    }
+
+    #[test]
+    fn interrupt_substitution() {
+        let (status, elapsed) = run_in_terminal("echo $(sleep 5)\nexit 7\n", b"\x03");
+        assert_eq!(status, WaitStatus::Exited(status.pid().unwrap(), 7));
+        assert!(elapsed < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn interrupt_pipeline() {
+        let src = "sleep 5 | while read l { }\nexit 7\n";
+        for keys in &[b"\x03", b"\x1a"] {
+            let (status, elapsed) = run_in_terminal(src, *keys);
+            assert_eq!(status, WaitStatus::Exited(status.pid().unwrap(), 7));
+            assert!(elapsed < Duration::from_secs(4));
+        }
+    }
+
+    #[test]
+    fn interrupt_read() {
+        let (status, elapsed) = run_in_terminal("read x || exit 7\n", b"\x03");
+        assert_eq!(status, WaitStatus::Exited(status.pid().unwrap(), 7));
+        assert!(elapsed < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn wait_for_finished_job() {
+        assert_eq!(
+            run("true &\na=$!\nsleep 0.2\nsleep 0.1 &\nwait $a && echo ok\n"),
+            "ok\n"
+        );
+        assert_eq!(
+            run("sh -c 'exit 3' &\na=$!\nsleep 0.2\ntrue &\nwait $a || echo failed\n"),
+            "failed\n"
+        );
+    }
+
+    #[test]
+    fn builtin_redirects() {
+        assert_eq!(
+            run("fn f { echo hi }\nf >/dev/null\necho after\n"),
+            "after\n"
+        );
+        assert_eq!(run("fn f { cat }\nf <<< hello\n"), "hello\n");
+        assert_eq!(run("cd / >/dev/null\npwd\n"), "/\n");
+    }
+
+    #[test]
+    fn builtins_in_pipelines() {
+        let src = "fn gen { echo b; echo a }\ngen | sort | tr a-z A-Z\necho x | cd /\npwd\n";
+        assert_eq!(
+            run(src),
+            format!("A\nB\n{}\n", env::current_dir().unwrap().display())
+        );
+        assert_eq!(run("fn f { cat }\necho piped | f | cat\n"), "piped\n");
+    }
+
+    #[test]
+    fn pipeline_ends() {
+        // `yes` only stops once `head` is gone and nobody else has the pipe.
+        assert_eq!(run("yes | head -1\n"), "y\n");
+
+        // Children start with SIGPIPE restored rather than ignored.
+        let status = run("grep SigIgn /proc/self/status\n");
+        let ignored = u64::from_str_radix(status.trim().rsplit('\t').next().unwrap(), 16);
+        assert_eq!(ignored.unwrap() & 1 << (libc::SIGPIPE - 1), 0);
+    }
+
+    #[test]
+    fn and_or_pipelines() {
+        assert_eq!(
+            run("seq 2 | while read l { echo $l } && echo ok\n"),
+            "1\n2\nok\n"
+        );
+        assert_eq!(run("echo a || echo b | while read l { echo $l }\n"), "a\n");
+        assert_eq!(
+            run("for i in 1 2 3 { true && test $i = 2 && break; echo $i }\n"),
+            "1\n"
+        );
+    }
+
+    #[test]
+    fn group_and_subshell_status() {
+        assert_eq!(
+            run("(exit 3) && echo no || echo yes\n{ false } || echo group\n"),
+            "yes\ngroup\n"
+        );
+    }
+
+    #[test]
+    fn match_arms() {
+        let src = "match abc { a* { echo first } abc { echo second } }\n";
+        assert_eq!(run(src), "first\n");
+        let src = "for x in b c { match $x { a | b { echo alt } * { echo default } } }\n";
+        assert_eq!(run(src), "alt\ndefault\n");
+        let src = "match x.rs { *.[ch] { echo c } *.rs { echo rust } }\n";
+        assert_eq!(run(src), "rust\n");
+    }
+
+    #[test]
+    fn match_quoted_pattern() {
+        let src = "match abc { \"a*\" { echo quoted } a?c { echo glob } }\n";
+        assert_eq!(run(src), "glob\n");
+        assert_eq!(run("match 'a*' { \"a*\" { echo literal } }\n"), "literal\n");
+        assert_eq!(
+            run("p='a*'\nmatch abc { \"$p\" { echo no } $p { echo yes } }\n"),
+            "yes\n"
+        );
+    }
+
+    #[test]
+    fn return_status() {
+        let src = "fn f { return 3; echo no }\nf || echo failed\nfn g { false; return 0 }\ng && echo ok\n";
+        assert_eq!(run(src), "failed\nok\n");
+        let src = "fn f { if true { while true { return 1 } } }\nf || echo nested\n";
+        assert_eq!(run(src), "nested\n");
+        assert_eq!(run("fn f { false; return }\nf || echo last\n"), "last\n");
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let src = "for i in 1 2 3 4 { if test $i = 2 { continue }\nif test $i = 4 { if true { break } }\necho $i }\n";
+        assert_eq!(run(src), "1\n3\n");
+        let src = "n=0\nwhile true { n=$((n + 1))\nif test $n = 2 { continue } else if test $n = 4 { break }\necho $n }\necho done $n\n";
+        assert_eq!(run(src), "1\n3\ndone 4\n");
+        // Only the innermost loop is left.
+        let src = "for i in a b { for j in 1 2 { if test $j = 2 { break }\necho $i$j } }\n";
+        assert_eq!(run(src), "a1\nb1\n");
+    }
+
+    #[test]
+    fn misplaced_control_flow() {
+        // These run in the test's own process, so they can't start any
+        // commands, whose SIGCHLD another thread might get.
+        let error = |src: &str| {
+            let mut shell = Interpreter::new().unwrap();
+            let program = parser::parse(src.as_bytes()).unwrap();
+            shell.execute(&program).unwrap_err().to_string()
+        };
+        assert_eq!(error("break\n"), "break: only meaningful in a loop");
+        assert_eq!(
+            error("{ continue }\n"),
+            "continue: only meaningful in a loop"
+        );
+        assert_eq!(
+            error("fn f { break }\nf\n"),
+            "break: only meaningful in a loop"
+        );
+        assert_eq!(
+            error("fn f { { continue } }\nfor i in 1 { f }\n"),
+            "continue: only meaningful in a loop"
+        );
+        assert_eq!(error("return\n"), "return: can only be used in a function");
+    }
+
+    fn split(line: &str, escaped: &[usize], count: usize) -> Vec<String> {
+        let line: Vec<_> = line
+            .bytes()
+            .enumerate()
+            .map(|(i, byte)| (byte, escaped.contains(&i)))
+            .collect();
+        split_fields(&line, count)
+            .into_iter()
+            .map(|field| String::from_utf8(field).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn read_fields() {
+        assert_eq!(split("  a  b\tc  ", &[], 2), vec!["a", "b\tc"]);
+        assert_eq!(split("a b c", &[], 3), vec!["a", "b", "c"]);
+        assert_eq!(split("a", &[], 3), vec!["a", "", ""]);
+        assert_eq!(split("a b c", &[1], 2), vec!["a b", "c"]);
+        assert_eq!(split("", &[], 1), vec![""]);
+    }
+
+    #[test]
+    fn read() {
+        let src = "printf 'a b\\nc\\n' | while read x y { echo \"$x|$y\" }\n";
+        assert_eq!(run(src), "a|b\nc|\n");
+        assert_eq!(
+            run("read -r a b <<< 'x\\y z w'\necho \"$a|$b\"\n"),
+            "x\\y|z w\n"
+        );
+        assert_eq!(run("read c <<< 'p\\ q r'\necho \"$c\"\n"), "p q r\n");
+        assert_eq!(
+            run("read <<< ' spaced '\necho \"[$REPLY]\"\n"),
+            "[spaced]\n"
+        );
+        assert_eq!(run("read 1x <<< a || echo failed\n"), "failed\n");
+        assert_eq!(run("read v < /dev/null || echo eof\n"), "eof\n");
+    }
+}
@@ -0,0 +1,403 @@
+use std::ffi::OsStr;
+use std::fmt;
+
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::{self, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use nix::Error::Sys;
+
+use crate::status::Status;
+use crate::Result;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    Running,
+    Stopped,
+    Done(Status),
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match *self {
+            State::Running => "Running",
+            State::Stopped => "Stopped",
+            State::Done(Status::Success) => "Done",
+            State::Done(Status::Failure) => "Failed",
+        };
+        // Pad here so that callers can align the column with `{:<24}`.
+        f.pad(s)
+    }
+}
+
+#[derive(Debug)]
+struct Process {
+    pid: Pid,
+    state: State,
+}
+
+/// A pipeline started by the shell. With job control its processes share a
+/// process group led by the first of them.
+#[derive(Debug)]
+pub struct Job {
+    id: usize,
+    processes: Vec<Process>,
+    text: String,
+    /// The state the user last heard about.
+    reported: State,
+}
+
+impl Job {
+    pub fn new(pids: Vec<Pid>, text: String) -> Self {
+        assert!(!pids.is_empty());
+        Self {
+            id: 0,
+            processes: pids
+                .into_iter()
+                .map(|pid| Process {
+                    pid,
+                    state: State::Running,
+                })
+                .collect(),
+            text,
+            reported: State::Running,
+        }
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn pgid(&self) -> Pid {
+        self.processes[0].pid
+    }
+
+    pub fn last_pid(&self) -> Pid {
+        self.processes[self.processes.len() - 1].pid
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The job is done once all of its processes are, with the status of the
+    /// last one, and stopped once none are left running.
+    pub fn state(&self) -> State {
+        if self.processes.iter().all(|p| p.state != State::Running) {
+            match self.processes[self.processes.len() - 1].state {
+                State::Done(status) if self.is_done() => State::Done(status),
+                _ => State::Stopped,
+            }
+        } else {
+            State::Running
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.processes
+            .iter()
+            .all(|p| matches!(p.state, State::Done(_)))
+    }
+
+    /// Collects any processes that have exited or stopped, without blocking.
+    pub fn update(&mut self) {
+        for process in &mut self.processes {
+            if let State::Done(_) = process.state {
+                continue;
+            }
+            let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED;
+            match wait::waitpid(process.pid, Some(flags)) {
+                Ok(WaitStatus::Exited(pid, code)) => {
+                    debug!("PID {} returned {}", pid, code);
+                    process.state = State::Done(code.into());
+                }
+                Ok(WaitStatus::Signaled(pid, signal, _)) => {
+                    debug!("PID {} received {:?}", pid, signal);
+                    process.state = State::Done(Status::Failure);
+                }
+                Ok(WaitStatus::Stopped(pid, signal)) => {
+                    debug!("PID {} stopped by {:?}", pid, signal);
+                    process.state = State::Stopped;
+                }
+                Ok(WaitStatus::StillAlive) => {}
+                Ok(status) => debug!("wait: {:?}", status),
+                Err(e) => {
+                    // Someone else reaped it, so there's nothing to wait for.
+                    debug!("wait: {}", e);
+                    process.state = State::Done(Status::Failure);
+                }
+            }
+        }
+    }
+
+    /// Blocks until every process in the job has exited.
+    pub fn wait(&mut self) -> Status {
+        for process in &mut self.processes {
+            while !matches!(process.state, State::Done(_)) {
+                process.state = match wait::waitpid(process.pid, None) {
+                    Ok(WaitStatus::Exited(_, code)) => State::Done(code.into()),
+                    Ok(WaitStatus::Signaled(..)) => State::Done(Status::Failure),
+                    Ok(_) | Err(Sys(Errno::EINTR)) => continue,
+                    Err(_) => State::Done(Status::Failure),
+                };
+            }
+        }
+        match self.state() {
+            State::Done(status) => status,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Sends SIGCONT to the job's process group.
+    pub fn resume(&mut self) -> Result<()> {
+        signal::kill(Pid::from_raw(-self.pgid().as_raw()), Signal::SIGCONT)?;
+        for process in &mut self.processes {
+            if process.state == State::Stopped {
+                process.state = State::Running;
+            }
+        }
+        self.reported = State::Running;
+        Ok(())
+    }
+}
+
+/// The jobs that are stopped or running in the background. The most
+/// recently added one is the current job, `%%` or `%+`, and the one before
+/// it is the previous job, `%-`.
+#[derive(Debug, Default)]
+pub struct Jobs {
+    jobs: Vec<Job>,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a job, numbering it if it's new, and returns its number.
+    pub fn add(&mut self, mut job: Job) -> usize {
+        if job.id == 0 {
+            job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        }
+        job.reported = job.state();
+        let id = job.id;
+        self.jobs.push(job);
+        id
+    }
+
+    /// Finds a job by a spec like `%2`, `%%`, `%+` or `%-`, or the current
+    /// job without one.
+    pub fn find(&self, spec: Option<&OsStr>) -> Option<usize> {
+        let len = self.jobs.len();
+        match spec.map(|spec| spec.to_str()) {
+            None | Some(Some("%%")) | Some(Some("%+")) => len.checked_sub(1),
+            Some(Some("%-")) => len.checked_sub(2),
+            Some(Some(spec)) if spec.starts_with('%') => {
+                let id: usize = spec[1..].parse().ok()?;
+                self.jobs.iter().position(|job| job.id == id)
+            }
+            _ => None,
+        }
+    }
+
+    /// Finds the job that a process belongs to.
+    pub fn find_pid(&self, pid: Pid) -> Option<usize> {
+        self.jobs
+            .iter()
+            .position(|job| job.processes.iter().any(|p| p.pid == pid))
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut Job {
+        &mut self.jobs[index]
+    }
+
+    pub fn remove(&mut self, index: usize) -> Job {
+        self.jobs.remove(index)
+    }
+
+    pub fn clear(&mut self) {
+        self.jobs.clear();
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = Job> + '_ {
+        self.jobs.drain(..)
+    }
+
+    /// Checks on every job. With `notify`, it reports the ones that have
+    /// finished or stopped since the user last heard about them and forgets
+    /// the finished ones. Otherwise finished jobs are kept along with their
+    /// status until `wait` collects them.
+    pub fn update(&mut self, notify: bool) {
+        for job in &mut self.jobs {
+            job.update();
+        }
+        if !notify {
+            return;
+        }
+        for index in 0..self.jobs.len() {
+            let state = self.jobs[index].state();
+            if state != self.jobs[index].reported && state != State::Running {
+                println!("{}", self.describe(index));
+            }
+            self.jobs[index].reported = state;
+        }
+        self.jobs.retain(|job| !job.is_done());
+    }
+
+    /// Lists every job for the `jobs` builtin, then forgets the finished ones.
+    pub fn print(&mut self) {
+        for job in &mut self.jobs {
+            job.update();
+        }
+        let mut order: Vec<_> = (0..self.jobs.len()).collect();
+        order.sort_by_key(|&index| self.jobs[index].id);
+        for index in order {
+            println!("{}", self.describe(index));
+            self.jobs[index].reported = self.jobs[index].state();
+        }
+        self.jobs.retain(|job| !job.is_done());
+    }
+
+    /// Describes a job the way `jobs` lists it, as in
+    /// `[1]+  Stopped                 vim notes.txt`.
+    pub fn describe(&self, index: usize) -> String {
+        let job = &self.jobs[index];
+        let marker = if index + 1 == self.jobs.len() {
+            '+'
+        } else if index + 2 == self.jobs.len() {
+            '-'
+        } else {
+            ' '
+        };
+        let state = job.state();
+        format!(
+            "[{}]{}  {:<24}{}{}",
+            job.id,
+            marker,
+            state,
+            job.text,
+            if state == State::Running { " &" } else { "" }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+
+    /// A job whose processes are already in the given states, so that nothing
+    /// needs to run. Its pids are made up.
+    fn job(states: &[State], text: &str) -> Job {
+        let pids = (0..states.len())
+            .map(|i| Pid::from_raw(100_000 + i as i32))
+            .collect();
+        let mut job = Job::new(pids, text.into());
+        for (process, &state) in job.processes.iter_mut().zip(states) {
+            process.state = state;
+        }
+        job
+    }
+
+    /// Starts a process for a job to reap, rather than waiting for it here.
+    #[allow(clippy::zombie_processes)]
+    fn spawn(program: &str, args: &[&str]) -> Pid {
+        let child = Command::new(program)
+            .args(args)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        Pid::from_raw(child.id() as i32)
+    }
+
+    #[test]
+    fn state() {
+        let done = State::Done(Status::Success);
+        let failed = State::Done(Status::Failure);
+        assert_eq!(job(&[State::Running], "").state(), State::Running);
+        assert_eq!(job(&[done, State::Running], "").state(), State::Running);
+        assert_eq!(job(&[State::Stopped, done], "").state(), State::Stopped);
+        assert_eq!(job(&[done, State::Stopped], "").state(), State::Stopped);
+        assert_eq!(job(&[done, failed], "").state(), failed);
+        assert_eq!(job(&[failed, done], "").state(), done);
+    }
+
+    #[test]
+    fn find() {
+        let mut jobs = Jobs::new();
+        assert_eq!(jobs.find(None), None);
+        assert_eq!(jobs.add(job(&[State::Running], "a")), 1);
+        assert_eq!(jobs.find(Some(OsStr::new("%-"))), None);
+        assert_eq!(jobs.add(job(&[State::Running], "b")), 2);
+        assert_eq!(jobs.add(job(&[State::Stopped], "c")), 3);
+
+        assert_eq!(jobs.find(None), Some(2));
+        assert_eq!(jobs.find(Some(OsStr::new("%%"))), Some(2));
+        assert_eq!(jobs.find(Some(OsStr::new("%+"))), Some(2));
+        assert_eq!(jobs.find(Some(OsStr::new("%-"))), Some(1));
+        assert_eq!(jobs.find(Some(OsStr::new("%1"))), Some(0));
+        for spec in &["%4", "%0", "%", "%x", "1", "+", ""] {
+            assert_eq!(jobs.find(Some(OsStr::new(spec))), None, "{}", spec);
+        }
+        assert_eq!(jobs.find_pid(Pid::from_raw(100_000)), Some(0));
+        assert_eq!(jobs.find_pid(Pid::from_raw(1)), None);
+
+        // A job that comes back keeps its number, and new ones follow the
+        // highest number in use.
+        let first = jobs.remove(0);
+        assert_eq!(jobs.find(Some(OsStr::new("%1"))), None);
+        assert_eq!(jobs.add(first), 1);
+        assert_eq!(jobs.find(Some(OsStr::new("%1"))), Some(2));
+        assert_eq!(jobs.add(job(&[State::Running], "d")), 4);
+    }
+
+    #[test]
+    fn describe() {
+        let mut jobs = Jobs::new();
+        jobs.add(job(&[State::Done(Status::Failure)], "false"));
+        jobs.add(job(&[State::Stopped], "vim notes.txt"));
+        jobs.add(job(
+            &[State::Done(Status::Success), State::Running],
+            "a | b",
+        ));
+        assert_eq!(jobs.describe(0), "[1]   Failed                  false");
+        assert_eq!(
+            jobs.describe(1),
+            "[2]-  Stopped                 vim notes.txt"
+        );
+        assert_eq!(jobs.describe(2), "[3]+  Running                 a | b &");
+    }
+
+    #[test]
+    fn update() {
+        let mut jobs = Jobs::new();
+        let sleep = spawn("sleep", &["10"]);
+        let exit = spawn("sh", &["-c", "exit 3"]);
+        jobs.add(Job::new(vec![sleep], "sleep 10".into()));
+        jobs.add(Job::new(vec![exit], "sh".into()));
+
+        let mut finished = Job::new(vec![spawn("true", &[])], "true".into());
+        for _ in 0..500 {
+            jobs.update(false);
+            finished.update();
+            if jobs.get_mut(1).state() != State::Running && finished.state() != State::Running {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Finished jobs are kept until they're waited for or reported.
+        assert_eq!(finished.state(), State::Done(Status::Success));
+        assert_eq!(jobs.find_pid(exit), Some(1));
+        assert_eq!(jobs.get_mut(1).state(), State::Done(Status::Failure));
+        jobs.update(true);
+        assert_eq!(jobs.find_pid(exit), None);
+        assert_eq!(jobs.find_pid(sleep), Some(0));
+        assert_eq!(jobs.get_mut(0).state(), State::Running);
+
+        signal::kill(sleep, Signal::SIGKILL).unwrap();
+        assert_eq!(jobs.get_mut(0).wait(), Status::Failure);
+    }
+}
@@ -1,8 +1,9 @@
 use std::fmt;
+use std::os::unix::io::RawFd;
 use std::slice::Iter;
 
-use crate::redirect::{Redirect, WriteMode};
-use crate::word::{Quote, Word};
+use crate::redirect::{Redirect, Target, WriteMode};
+use crate::word::{self, Quote, Word};
 use crate::Result;
 
 pub struct Lexer<'input> {
@@ -11,6 +12,11 @@ pub struct Lexer<'input> {
     peek: Option<u8>,
     next: Option<Kind>,
     last: Option<Kind>,
+    /// Bytes of here-document bodies to skip at the end of the current line.
+    heredocs: usize,
+    /// Subshells that are still open. Outside of one, `)` is just a byte of
+    /// a word, as in `echo :)`.
+    parens: usize,
 }
 
 impl<'input> Lexer<'input> {
@@ -21,6 +27,8 @@ impl<'input> Lexer<'input> {
             peek: None,
             next: None,
             last: None,
+            heredocs: 0,
+            parens: 0,
         }
     }
 
@@ -29,14 +37,35 @@ impl<'input> Lexer<'input> {
         Some(Ok(Token::new(kind, line.unwrap_or(self.line))))
     }
 
+    /// Emits an operator that must be followed by another command, allowing
+    /// that command to start on the next line.
+    fn emit_operator(&mut self, kind: Kind) -> Option<Result<Token>> {
+        let line = self.line;
+        self.consume_line_terminators();
+        self.emit(kind, Some(line))
+    }
+
     fn next_byte(&mut self) -> Option<u8> {
         let next = self.peek.take().or_else(|| self.src.next().cloned());
         if next == Some(b'\n') {
             self.line += 1;
+            if self.heredocs > 0 {
+                self.skip_heredocs();
+            }
         }
         next
     }
 
+    /// Skips the bodies of the here-documents on the line that just ended,
+    /// since they've already been read.
+    fn skip_heredocs(&mut self) {
+        let rest = self.src.as_slice();
+        let (bodies, rest) = rest.split_at(self.heredocs);
+        self.line += bodies.iter().filter(|&&b| b == b'\n').count();
+        self.src = rest.iter();
+        self.heredocs = 0;
+    }
+
     fn push_byte(&mut self, byte: u8) {
         assert!(self.peek.is_none());
         if byte == b'\n' {
@@ -54,11 +83,32 @@ impl<'input> Lexer<'input> {
         }
     }
 
+    /// Skips to the end of the line, leaving the line terminator in place so
+    /// that it still ends the statement.
+    fn consume_comment(&mut self) {
+        while let Some(byte) = self.next_byte() {
+            if byte == b'\n' {
+                self.push_byte(byte);
+                break;
+            }
+        }
+    }
+
     fn consume_quoted_word(&mut self, quote: u8) -> Option<Result<Token>> {
         let line = self.line;
         let mut buf = Vec::new();
 
         while let Some(byte) = self.next_byte() {
+            if byte == b'\\' && quote == b'"' {
+                self.consume_escape(&mut buf);
+                continue;
+            }
+            if byte == b'$' && quote == b'"' {
+                if let Err(e) = self.consume_substitution(&mut buf) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
             if byte == quote {
                 let quote = if quote == b'"' {
                     Quote::Double
@@ -80,81 +130,236 @@ impl<'input> Lexer<'input> {
         )))
     }
 
-    fn consume_redirect(&mut self, fd: Stream) -> Result<(Redirect<Word>, usize)> {
-        let line = self.line;
+    /// Keeps a backslash escape in the word so that expansion can tell escaped
+    /// bytes apart. A backslash-newline continues the line and is dropped.
+    fn consume_escape(&mut self, buf: &mut Vec<u8>) {
+        match self.next_byte() {
+            Some(b'\n') => {}
+            Some(byte) => {
+                buf.push(b'\\');
+                buf.push(byte);
+            }
+            None => buf.push(b'\\'),
+        }
+    }
 
-        let mode = if fd.is_writable() {
-            match self.next_byte() {
-                Some(b'>') => WriteMode::Append,
-                Some(byte) => {
-                    self.push_byte(byte);
-                    WriteMode::Truncate
-                }
-                None => WriteMode::Truncate,
+    /// Keeps a `$(...)` command substitution or `${...}` parameter expansion
+    /// in the word, along with any whitespace and operators inside it.
+    fn consume_substitution(&mut self, buf: &mut Vec<u8>) -> Result<()> {
+        self.consume_enclosed(b'$', buf)
+    }
+
+    /// Keeps a `$(...)`, `${...}`, `<(...)` or `>(...)` in the word, where
+    /// `intro` is the byte before the opening bracket.
+    fn consume_enclosed(&mut self, intro: u8, buf: &mut Vec<u8>) -> Result<()> {
+        buf.push(intro);
+        let open = match self.next_byte() {
+            Some(b'(') => b'(',
+            Some(b'{') if intro == b'$' => b'{',
+            Some(byte) => {
+                self.push_byte(byte);
+                return Ok(());
             }
-        } else {
-            WriteMode::Truncate
+            None => return Ok(()),
         };
+        buf.push(open);
 
-        let location = self.consume_redirect_location()?;
-
-        if let Location::Stream(stream) = location {
-            if mode == WriteMode::Append {
-                bail!("cannot open {} in append mode", stream);
+        assert!(self.peek.is_none());
+        let src = self.src.as_slice();
+        let len = if open == b'(' {
+            match word::find_closing_paren(src) {
+                Some(len) => len,
+                None if intro == b'$' => bail!("missing closing ')' for command substitution"),
+                None => bail!("missing closing ')' for process substitution"),
             }
+        } else {
+            match word::find_closing_brace(src) {
+                Some(len) => len,
+                None => bail!("missing closing '}}' for parameter expansion"),
+            }
+        };
+        for _ in 0..=len {
+            buf.push(self.next_byte().unwrap());
         }
+        Ok(())
+    }
 
-        let redirect = match fd {
-            Stream::Stdin => match location {
-                Location::Path(path) => Redirect::InFile(path),
-                Location::Stream(stream) => bail!("cannot redirect stdin to {}", stream),
-            },
-            Stream::Stdout => match location {
-                Location::Path(path) => Redirect::OutFile(path, mode),
-                Location::Stream(WritableStream::Stdout) => {
-                    bail!("cannot redirect stdout to itself")
+    fn emit_redirect(&mut self, fd: Option<RawFd>, op: u8) -> Option<Result<Token>> {
+        match self.consume_redirect(fd, op) {
+            Ok((redirect, line)) => self.emit(Kind::Redirect(redirect), Some(line)),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Reads the rest of a redirect after its `<` or `>`. Without an explicit
+    /// file descriptor, `<` redirects stdin and `>` stdout.
+    fn consume_redirect(&mut self, fd: Option<RawFd>, op: u8) -> Result<(Redirect<Word>, usize)> {
+        let line = self.line;
+
+        let redirect = if op == b'<' {
+            let fd = fd.unwrap_or(libc::STDIN_FILENO);
+            let target = match self.next_byte() {
+                Some(b'<') => self.consume_here_redirect()?,
+                Some(b'>') => Target::ReadWrite(self.consume_redirect_word("redirect location")?),
+                Some(b'&') => self.consume_dup_target()?,
+                byte => {
+                    if let Some(byte) = byte {
+                        self.push_byte(byte);
+                    }
+                    Target::Input(self.consume_redirect_word("redirect location")?)
                 }
-                Location::Stream(WritableStream::Stderr) => Redirect::OutErr,
-            },
-            Stream::Stderr => match location {
-                Location::Path(path) => Redirect::ErrFile(path, mode),
-                Location::Stream(WritableStream::Stdout) => Redirect::ErrOut,
-                Location::Stream(WritableStream::Stderr) => {
-                    bail!("cannot redirect stderr to itself")
+            };
+            Redirect::new(fd, target)
+        } else {
+            let fd = fd.unwrap_or(libc::STDOUT_FILENO);
+            let mode = match self.next_byte() {
+                Some(b'>') => WriteMode::Append,
+                Some(b'|') => WriteMode::Clobber,
+                Some(b'&') => return Ok((Redirect::new(fd, self.consume_dup_target()?), line)),
+                byte => {
+                    if let Some(byte) = byte {
+                        self.push_byte(byte);
+                    }
+                    WriteMode::Truncate
                 }
-            },
+            };
+            let path = self.consume_redirect_word("redirect location")?;
+            Redirect::new(fd, Target::Output(path, mode))
         };
         Ok((redirect, line))
     }
 
-    fn consume_redirect_location(&mut self) -> Result<Location> {
-        let location = match self.next_byte() {
-            Some(b'&') => match self.next_byte() {
-                Some(b'1') => Location::Stream(WritableStream::Stdout),
-                Some(b'2') => Location::Stream(WritableStream::Stderr),
-                Some(byte) => bail!(
-                    "expected 1 (stdout) or 2 (stderr), found '{}'",
-                    byte as char
-                ),
-                None => bail!("expected 1 (stdout) or 2 (stderr)"),
+    /// Reads `&>file` or `&>>file`, which redirect stdout to the file and then
+    /// stderr to stdout. The stderr redirect is emitted as the next token.
+    fn consume_redirect_both(&mut self) -> Result<(Redirect<Word>, usize)> {
+        let (redirect, line) = self.consume_redirect(Some(libc::STDOUT_FILENO), b'>')?;
+        if let Target::Fd(_) | Target::Close = redirect.target {
+            bail!("expected redirect location after '&>'");
+        }
+        self.next = Some(Kind::Redirect(Redirect::new(
+            libc::STDERR_FILENO,
+            Target::Fd(libc::STDOUT_FILENO),
+        )));
+        Ok((redirect, line))
+    }
+
+    /// Reads what follows `>&` or `<&`: a file descriptor to duplicate, or
+    /// `-` to close.
+    fn consume_dup_target(&mut self) -> Result<Target<Word>> {
+        let mut digits = Vec::new();
+        while let Some(byte) = self.next_byte() {
+            match byte {
+                b'-' if digits.is_empty() => return Ok(Target::Close),
+                b'0'..=b'9' => digits.push(byte),
+                _ => {
+                    self.push_byte(byte);
+                    break;
+                }
+            }
+        }
+        if digits.is_empty() {
+            bail!("expected a file descriptor or '-' after '&'");
+        }
+        Ok(Target::Fd(parse_fd(&digits)?))
+    }
+
+    fn consume_redirect_word(&mut self, expected: &str) -> Result<Word> {
+        match self.next() {
+            Some(token) => match token?.kind {
+                Kind::Word(word) => Ok(word),
+                kind => bail!("expected {}, found {}", expected, kind),
             },
-            Some(other) => {
-                self.push_byte(other);
-                let token = self.next().expect("expected token")?;
-                if let Kind::Word(word) = token.kind {
-                    Location::Path(word)
-                } else {
-                    bail!("expected redirect location, found {}", token.kind);
+            None => bail!("expected {}", expected),
+        }
+    }
+
+    /// Reads a here-string `<<< word`, or a here-document `<<TAG` whose body
+    /// is taken from the lines after the current one. With `<<-`, leading
+    /// tabs are stripped from the body and the terminating line.
+    fn consume_here_redirect(&mut self) -> Result<Target<Word>> {
+        let strip_tabs = match self.next_byte() {
+            Some(b'<') => {
+                return Ok(Target::HereString(
+                    self.consume_redirect_word("here-string")?,
+                ));
+            }
+            Some(b'-') => true,
+            Some(byte) => {
+                self.push_byte(byte);
+                false
+            }
+            None => false,
+        };
+
+        // Quoting any part of the tag disables expansion in the body.
+        let tag = self.consume_redirect_word("here-document tag")?;
+        let quoted = tag.quote.is_some() || tag.as_bytes().contains(&b'\\');
+        let tag: Vec<u8> = tag
+            .as_bytes()
+            .iter()
+            .cloned()
+            .filter(|&b| b != b'\\')
+            .collect();
+
+        let rest = self.src.as_slice();
+        let start = if self.peek == Some(b'\n') {
+            0
+        } else {
+            match rest.iter().position(|&b| b == b'\n') {
+                Some(pos) => pos + 1,
+                None => rest.len(),
+            }
+        };
+
+        let mut body = Vec::new();
+        let mut pos = start + self.heredocs;
+        loop {
+            if pos >= rest.len() {
+                bail!(
+                    "missing here-document terminator: {}",
+                    String::from_utf8_lossy(&tag)
+                );
+            }
+            let end = rest[pos..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(rest.len(), |len| pos + len);
+            let mut line = &rest[pos..end];
+            pos = rest.len().min(end + 1);
+            if strip_tabs {
+                while line.first() == Some(&b'\t') {
+                    line = &line[1..];
                 }
             }
-            None => bail!("expected redirect location"),
+            if line == tag.as_slice() {
+                break;
+            }
+            body.extend_from_slice(line);
+            body.push(b'\n');
+        }
+        self.heredocs = pos - start;
+
+        let quote = if quoted { Quote::Single } else { Quote::Double };
+        Ok(Target::HereDoc(Word::new(body, quote)))
+    }
+
+    /// Emits a `}` or `)`, ending the last statement before it if needed.
+    fn emit_closing(&mut self, kind: Kind) -> Option<Result<Token>> {
+        let kind = if self.should_insert_semi() {
+            self.next = Some(kind);
+            Kind::Semi
+        } else {
+            kind
         };
-        Ok(location)
+        self.emit(kind, None)
     }
 
     fn should_insert_semi(&self) -> bool {
         match self.last {
-            Some(ref kind) => *kind != Kind::LeftBrace && *kind != Kind::Semi,
+            Some(ref kind) => !matches!(
+                *kind,
+                Kind::LeftBrace | Kind::LeftParen | Kind::Semi | Kind::And | Kind::Or
+            ),
             None => false,
         }
     }
@@ -171,76 +376,122 @@ impl<'input> Iterator for Lexer<'input> {
         let mut buf = Vec::new();
 
         while let Some(byte) = self.next_byte() {
+            if byte == b'\\' {
+                self.consume_escape(&mut buf);
+                continue;
+            }
+            if byte == b'$' {
+                if let Err(e) = self.consume_substitution(&mut buf) {
+                    return Some(Err(e));
+                }
+                continue;
+            }
+
             if buf.is_empty() {
                 match byte {
                     b'"' | b'\'' => return self.consume_quoted_word(byte),
-                    b'{' => {
+                    b'#' => {
+                        self.consume_comment();
+                        continue;
+                    }
+                    b'{' => match self.next_byte() {
+                        // A brace followed by anything else starts a word like
+                        // `{a,b}.txt` that's subject to brace expansion.
+                        Some(next) if !next.is_ascii_whitespace() && !is_block_delimiter(next) => {
+                            self.push_byte(next);
+                        }
+                        next => {
+                            if let Some(next) = next {
+                                self.push_byte(next);
+                            }
+                            let line = self.line;
+                            self.consume_line_terminators();
+                            return self.emit(Kind::LeftBrace, Some(line));
+                        }
+                    },
+                    b'}' => return self.emit_closing(Kind::RightBrace),
+                    b'(' => {
+                        self.parens += 1;
                         let line = self.line;
                         self.consume_line_terminators();
-                        return self.emit(Kind::LeftBrace, Some(line));
-                    }
-                    b'}' => {
-                        let kind = if self.should_insert_semi() {
-                            self.next = Some(Kind::RightBrace);
-                            Kind::Semi
-                        } else {
-                            Kind::RightBrace
-                        };
-                        return self.emit(kind, None);
+                        return self.emit(Kind::LeftParen, Some(line));
                     }
-                    b'|' => return self.emit(Kind::Pipe, None),
-                    b @ b'>' | b @ b'<' => {
-                        return match self.consume_redirect(if b == b'>' {
-                            Stream::Stdout
-                        } else {
-                            Stream::Stdin
-                        }) {
-                            Ok((redirect, line)) => self.emit(Kind::Redirect(redirect), Some(line)),
-                            Err(e) => Some(Err(e)),
-                        };
+                    b')' if self.parens > 0 => {
+                        self.parens -= 1;
+                        return self.emit_closing(Kind::RightParen);
                     }
-                    fd @ b'0' => match self.next_byte() {
-                        Some(b'<') => {
-                            return match self.consume_redirect(Stream::Stdin) {
-                                Ok((redirect, line)) => {
-                                    self.emit(Kind::Redirect(redirect), Some(line))
-                                }
-                                Err(e) => Some(Err(e)),
-                            };
+                    b'|' => match self.next_byte() {
+                        Some(b'|') => return self.emit_operator(Kind::Or),
+                        Some(b'&') => {
+                            // `|&` pipes stderr along with stdout, like `2>&1 |`.
+                            self.next = Some(Kind::Pipe);
+                            let redirect =
+                                Redirect::new(libc::STDERR_FILENO, Target::Fd(libc::STDOUT_FILENO));
+                            return self.emit(Kind::Redirect(redirect), None);
                         }
                         Some(other) => {
-                            buf.push(fd);
                             self.push_byte(other);
-                            continue;
-                        }
-                        None => {
-                            buf.push(fd);
-                            break;
+                            return self.emit(Kind::Pipe, None);
                         }
+                        None => return self.emit(Kind::Pipe, None),
                     },
-                    fd @ b'1' | fd @ b'2' => match self.next_byte() {
+                    b'&' => match self.next_byte() {
+                        Some(b'&') => return self.emit_operator(Kind::And),
                         Some(b'>') => {
-                            return match self.consume_redirect(if fd == b'1' {
-                                Stream::Stdout
-                            } else {
-                                Stream::Stderr
-                            }) {
+                            return match self.consume_redirect_both() {
                                 Ok((redirect, line)) => {
                                     self.emit(Kind::Redirect(redirect), Some(line))
                                 }
                                 Err(e) => Some(Err(e)),
                             };
                         }
-                        Some(other) => {
-                            buf.push(fd);
-                            self.push_byte(other);
+                        next => {
+                            if let Some(next) = next {
+                                self.push_byte(next);
+                            }
+                            // A lone `&` runs what comes before it in the
+                            // background and ends the statement.
+                            self.next = Some(Kind::Semi);
+                            return self.emit(Kind::Background, None);
+                        }
+                    },
+                    b'<' | b'>' => match self.next_byte() {
+                        Some(b'(') => {
+                            self.push_byte(b'(');
+                            if let Err(e) = self.consume_enclosed(byte, &mut buf) {
+                                return Some(Err(e));
+                            }
                             continue;
                         }
-                        None => {
-                            buf.push(fd);
-                            break;
+                        next => {
+                            if let Some(next) = next {
+                                self.push_byte(next);
+                            }
+                            return self.emit_redirect(None, byte);
                         }
                     },
+                    b'0'..=b'9' => {
+                        // Digits right before `<` or `>` are the file
+                        // descriptor to redirect, as in `2>err.log`.
+                        let mut digits = vec![byte];
+                        while let Some(next) = self.next_byte() {
+                            match next {
+                                b'0'..=b'9' => digits.push(next),
+                                b'<' | b'>' => {
+                                    return match parse_fd(&digits) {
+                                        Ok(fd) => self.emit_redirect(Some(fd), next),
+                                        Err(e) => Some(Err(e)),
+                                    };
+                                }
+                                _ => {
+                                    self.push_byte(next);
+                                    break;
+                                }
+                            }
+                        }
+                        buf.extend(digits);
+                        continue;
+                    }
                     _ => {}
                 }
             }
@@ -249,11 +500,11 @@ impl<'input> Iterator for Lexer<'input> {
                 if buf.is_empty() {
                     let line = self.line;
                     self.consume_line_terminators();
-                    return if self.last.is_none() {
-                        // Don't emit leading delimiters.
-                        self.next()
-                    } else {
+                    return if self.should_insert_semi() {
                         self.emit(Kind::Semi, Some(line - 1))
+                    } else {
+                        // Don't emit leading or redundant delimiters.
+                        self.next()
                     };
                 } else {
                     self.push_byte(byte);
@@ -261,6 +512,12 @@ impl<'input> Iterator for Lexer<'input> {
                 }
             }
 
+            if byte == b')' && self.parens > 0 {
+                // A `)` ends the word inside a subshell, as in `(cd src; make)`.
+                self.push_byte(byte);
+                break;
+            }
+
             if byte.is_ascii_whitespace() {
                 if buf.is_empty() {
                     // Ignore consecutive whitespace.
@@ -292,6 +549,20 @@ fn is_line_terminator(byte: u8) -> bool {
     byte == b'\n' || byte == b';'
 }
 
+fn parse_fd(digits: &[u8]) -> Result<RawFd> {
+    match String::from_utf8_lossy(digits).parse() {
+        Ok(fd) => Ok(fd),
+        Err(_) => bail!(
+            "file descriptor out of range: {}",
+            String::from_utf8_lossy(digits)
+        ),
+    }
+}
+
+fn is_block_delimiter(byte: u8) -> bool {
+    is_line_terminator(byte) || byte == b'}'
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Token {
     pub kind: Kind,
@@ -310,7 +581,12 @@ pub enum Kind {
     Redirect(Redirect<Word>),
     LeftBrace,
     RightBrace,
+    LeftParen,
+    RightParen,
     Pipe,
+    And,
+    Or,
+    Background,
     Semi,
 }
 
@@ -321,7 +597,12 @@ impl fmt::Display for Kind {
             Kind::Redirect(ref redirect) => redirect.to_string(),
             Kind::LeftBrace => "{".into(),
             Kind::RightBrace => "}".into(),
+            Kind::LeftParen => "(".into(),
+            Kind::RightParen => ")".into(),
             Kind::Pipe => "|".into(),
+            Kind::And => "&&".into(),
+            Kind::Or => "||".into(),
+            Kind::Background => "&".into(),
             Kind::Semi => ";".into(),
         };
 
@@ -329,43 +610,6 @@ impl fmt::Display for Kind {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum Stream {
-    Stdin,
-    Stdout,
-    Stderr,
-}
-
-impl Stream {
-    fn is_writable(self) -> bool {
-        match self {
-            Stream::Stdin => false,
-            Stream::Stdout | Stream::Stderr => true,
-        }
-    }
-}
-
-#[derive(Clone, Debug, PartialEq)]
-enum Location {
-    Path(Word),
-    Stream(WritableStream),
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum WritableStream {
-    Stdout,
-    Stderr,
-}
-
-impl fmt::Display for WritableStream {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            WritableStream::Stdout => write!(f, "stdout"),
-            WritableStream::Stderr => write!(f, "stderr"),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -508,6 +752,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn else_on_next_line_and_until() {
+        let tokens: Vec<Token> = Lexer::new(b"if a {\n}\n\nelse {\n}\nuntil b { }\n")
+            .map(|t| t.unwrap())
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(Kind::Word("if".into()), 1),
+                Token::new(Kind::Word("a".into()), 1),
+                Token::new(Kind::LeftBrace, 1),
+                Token::new(Kind::RightBrace, 2),
+                Token::new(Kind::Semi, 2),
+                Token::new(Kind::Word("else".into()), 4),
+                Token::new(Kind::LeftBrace, 4),
+                Token::new(Kind::RightBrace, 5),
+                Token::new(Kind::Semi, 5),
+                Token::new(Kind::Word("until".into()), 6),
+                Token::new(Kind::Word("b".into()), 6),
+                Token::new(Kind::LeftBrace, 6),
+                Token::new(Kind::RightBrace, 6),
+                Token::new(Kind::Semi, 6),
+            ],
+        );
+    }
+
     #[test]
     fn pipeline() {
         let tokens: Vec<Kind> = Lexer::new(b"echo foo | cat\n")
@@ -524,4 +794,370 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn redirects() {
+        let src = b"cmd <in >out 2>>log 3>&1 <&3 2>&- 10<>rw 12word 1>&2 >|force\n";
+        let tokens: Vec<Kind> = Lexer::new(src).map(|t| t.unwrap().kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("cmd".into()),
+                Kind::Redirect(Redirect::new(0, Target::Input("in".into()))),
+                Kind::Redirect(Redirect::new(
+                    1,
+                    Target::Output("out".into(), WriteMode::Truncate)
+                )),
+                Kind::Redirect(Redirect::new(
+                    2,
+                    Target::Output("log".into(), WriteMode::Append)
+                )),
+                Kind::Redirect(Redirect::new(3, Target::Fd(1))),
+                Kind::Redirect(Redirect::new(0, Target::Fd(3))),
+                Kind::Redirect(Redirect::new(2, Target::Close)),
+                Kind::Redirect(Redirect::new(10, Target::ReadWrite("rw".into()))),
+                Kind::Word("12word".into()),
+                Kind::Redirect(Redirect::new(1, Target::Fd(2))),
+                Kind::Redirect(Redirect::new(
+                    1,
+                    Target::Output("force".into(), WriteMode::Clobber)
+                )),
+                Kind::Semi,
+            ],
+        );
+
+        for src in &[
+            &b"cmd >&"[..],
+            b"cmd 2>&x",
+            b"cmd 99999999999>out",
+            b"cmd >",
+        ] {
+            assert!(Lexer::new(src).any(|t| t.is_err()));
+        }
+    }
+
+    #[test]
+    fn redirect_stdout_and_stderr() {
+        let tokens: Vec<Kind> = Lexer::new(b"a &>out; b &>>log |& c\n")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        let err_out = Kind::Redirect(Redirect::new(2, Target::Fd(1)));
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("a".into()),
+                Kind::Redirect(Redirect::new(
+                    1,
+                    Target::Output("out".into(), WriteMode::Truncate)
+                )),
+                err_out.clone(),
+                Kind::Semi,
+                Kind::Word("b".into()),
+                Kind::Redirect(Redirect::new(
+                    1,
+                    Target::Output("log".into(), WriteMode::Append)
+                )),
+                err_out.clone(),
+                err_out,
+                Kind::Pipe,
+                Kind::Word("c".into()),
+                Kind::Semi,
+            ],
+        );
+
+        assert!(Lexer::new(b"a &>&2").any(|t| t.is_err()));
+    }
+
+    #[test]
+    fn here_documents() {
+        let src =
+            b"cat <<EOF; echo $x\nhello $x\n  EOF\nEOF\ncat <<-'END' <<< word\n\tbody\n\tEND\n";
+        let tokens: Vec<Token> = Lexer::new(src).map(|t| t.unwrap()).collect();
+        assert_eq!(
+            tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>(),
+            vec![
+                Kind::Word("cat".into()),
+                Kind::Redirect(Redirect::new(
+                    0,
+                    Target::HereDoc(Word::new(&b"hello $x\n  EOF\n"[..], Quote::Double))
+                )),
+                Kind::Semi,
+                Kind::Word("echo".into()),
+                Kind::Word("$x".into()),
+                Kind::Semi,
+                Kind::Word("cat".into()),
+                Kind::Redirect(Redirect::new(
+                    0,
+                    Target::HereDoc(Word::new(&b"body\n"[..], Quote::Single))
+                )),
+                Kind::Redirect(Redirect::new(0, Target::HereString("word".into()))),
+                Kind::Semi,
+            ],
+        );
+        // Lines inside the body still count.
+        assert_eq!(tokens[6].line, 5);
+
+        assert!(Lexer::new(b"cat <<EOF\nbody\n").any(|t| t.is_err()));
+    }
+
+    #[test]
+    fn and_or_list() {
+        let tokens: Vec<Token> = Lexer::new(b"make && ./run ||\n  echo failed\n")
+            .map(|t| t.unwrap())
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(Kind::Word("make".into()), 1),
+                Token::new(Kind::And, 1),
+                Token::new(Kind::Word("./run".into()), 1),
+                Token::new(Kind::Or, 1),
+                Token::new(Kind::Word("echo".into()), 2),
+                Token::new(Kind::Word("failed".into()), 2),
+                Token::new(Kind::Semi, 2),
+            ],
+        );
+    }
+
+    #[test]
+    fn single_ampersand_runs_in_background() {
+        let tokens: Vec<Kind> = Lexer::new(b"sleep 1 & echo a&&b &\n")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("sleep".into()),
+                Kind::Word("1".into()),
+                Kind::Background,
+                Kind::Semi,
+                Kind::Word("echo".into()),
+                Kind::Word("a&&b".into()),
+                Kind::Background,
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn brace_words() {
+        let tokens: Vec<Kind> = Lexer::new(b"if true {echo {a,b} x{1..3} }\nwhile x {}")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("if".into()),
+                Kind::Word("true".into()),
+                Kind::Word("{echo".into()),
+                Kind::Word("{a,b}".into()),
+                Kind::Word("x{1..3}".into()),
+                Kind::Semi,
+                Kind::RightBrace,
+                Kind::Semi,
+                Kind::Word("while".into()),
+                Kind::Word("x".into()),
+                Kind::LeftBrace,
+                Kind::RightBrace,
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn groups() {
+        let tokens: Vec<Kind> = Lexer::new(b"{ echo a } >out\n(\ncd src; make)\n")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::LeftBrace,
+                Kind::Word("echo".into()),
+                Kind::Word("a".into()),
+                Kind::Semi,
+                Kind::RightBrace,
+                Kind::Redirect(Redirect::new(
+                    1,
+                    Target::Output("out".into(), WriteMode::Truncate)
+                )),
+                Kind::Semi,
+                Kind::LeftParen,
+                Kind::Word("cd".into()),
+                Kind::Word("src".into()),
+                Kind::Semi,
+                Kind::Word("make".into()),
+                Kind::Semi,
+                Kind::RightParen,
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn right_paren_outside_subshell() {
+        let tokens: Vec<Kind> = Lexer::new(b"echo a)b :) )\n(echo (a)b) c)\n")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word("a)b".into()),
+                Kind::Word(":)".into()),
+                Kind::Word(")".into()),
+                Kind::Semi,
+                Kind::LeftParen,
+                Kind::Word("echo".into()),
+                Kind::LeftParen,
+                Kind::Word("a".into()),
+                Kind::Semi,
+                Kind::RightParen,
+                Kind::Word("b".into()),
+                Kind::Semi,
+                Kind::RightParen,
+                Kind::Word("c)".into()),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn comments() {
+        let src = br#"#!/usr/bin/env msh
+# Greet everyone.
+echo hello # world
+if true { # comment after a brace
+  echo a#b $#
+}
+"#;
+        let tokens: Vec<Token> = Lexer::new(src).map(|t| t.unwrap()).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(Kind::Word("echo".into()), 3),
+                Token::new(Kind::Word("hello".into()), 3),
+                Token::new(Kind::Semi, 3),
+                Token::new(Kind::Word("if".into()), 4),
+                Token::new(Kind::Word("true".into()), 4),
+                Token::new(Kind::LeftBrace, 4),
+                Token::new(Kind::Word("echo".into()), 5),
+                Token::new(Kind::Word("a#b".into()), 5),
+                Token::new(Kind::Word("$#".into()), 5),
+                Token::new(Kind::Semi, 5),
+                Token::new(Kind::RightBrace, 6),
+                Token::new(Kind::Semi, 6),
+            ],
+        );
+    }
+
+    #[test]
+    fn comment_without_newline() {
+        let tokens: Vec<Kind> = Lexer::new(b"ls # list files")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(tokens, vec![Kind::Word("ls".into()), Kind::Semi]);
+
+        let tokens: Vec<Kind> = Lexer::new(b"# nothing to do")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(tokens, Vec::new());
+    }
+
+    #[test]
+    fn escapes() {
+        let src = br#"echo a\ b \{ \# "say \"hi\" for \$5" '\n'"#;
+        let tokens: Vec<Kind> = Lexer::new(src).map(|t| t.unwrap().kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word(r"a\ b".into()),
+                Kind::Word(r"\{".into()),
+                Kind::Word(r"\#".into()),
+                Kind::Word(Word::new(r#"say \"hi\" for \$5"#, Quote::Double)),
+                Kind::Word(Word::new(r"\n", Quote::Single)),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn line_continuation() {
+        let src = b"./configure \\\n  --prefix=/usr \\\n  --quiet\necho \"a\\\nb\"\n";
+        let tokens: Vec<Token> = Lexer::new(src).map(|t| t.unwrap()).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::new(Kind::Word("./configure".into()), 1),
+                Token::new(Kind::Word("--prefix=/usr".into()), 2),
+                Token::new(Kind::Word("--quiet".into()), 3),
+                Token::new(Kind::Semi, 3),
+                Token::new(Kind::Word("echo".into()), 4),
+                Token::new(Kind::Word(Word::new("ab", Quote::Double)), 4),
+                Token::new(Kind::Semi, 5),
+            ],
+        );
+    }
+
+    #[test]
+    fn command_substitution() {
+        let src = b"export REV=$(git rev-parse HEAD) \"on $(date +%F; echo \")\")\" $HOME\n";
+        let tokens: Vec<Kind> = Lexer::new(src).map(|t| t.unwrap().kind).collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("export".into()),
+                Kind::Word("REV=$(git rev-parse HEAD)".into()),
+                Kind::Word(Word::new(r#"on $(date +%F; echo ")")"#, Quote::Double)),
+                Kind::Word("$HOME".into()),
+                Kind::Semi,
+            ],
+        );
+    }
+
+    #[test]
+    fn parameter_expansion() {
+        let tokens: Vec<Kind> = Lexer::new(b"echo ${X:-a b} \"${Y:-\"c\"}\"\n")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("echo".into()),
+                Kind::Word("${X:-a b}".into()),
+                Kind::Word(Word::new(&b"${Y:-\"c\"}"[..], Quote::Double)),
+                Kind::Semi,
+            ],
+        );
+        assert!(Lexer::new(b"echo ${X").nth(1).unwrap().is_err());
+    }
+
+    #[test]
+    fn process_substitution() {
+        let tokens: Vec<Kind> = Lexer::new(b"diff <(sort a) >(tee b | cat) < <(ls)\n")
+            .map(|t| t.unwrap().kind)
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                Kind::Word("diff".into()),
+                Kind::Word("<(sort a)".into()),
+                Kind::Word(">(tee b | cat)".into()),
+                Kind::Redirect(Redirect::new(0, Target::Input("<(ls)".into()))),
+                Kind::Semi,
+            ],
+        );
+        assert!(Lexer::new(b"cat <(ls").any(|t| t.is_err()));
+    }
+
+    #[test]
+    fn command_substitution_unclosed() {
+        let mut lexer = Lexer::new(b"echo $(date");
+        assert_eq!(
+            lexer.next().unwrap().unwrap().kind,
+            Kind::Word("echo".into())
+        );
+        assert!(lexer.next().unwrap().is_err());
+    }
 }
@@ -8,13 +8,18 @@ macro_rules! display {
     ($fmt:expr, $($arg:tt)*) => (eprintln!(concat!(env!("CARGO_PKG_NAME"), ": ", $fmt), $($arg)*));
 }
 
+mod arith;
 mod ast;
+mod brace;
 mod command;
 mod cwd;
 mod environment;
+mod glob;
 mod history;
 mod interpreter;
+mod job;
 mod lexer;
+mod options;
 mod parser;
 mod redirect;
 mod status;
@@ -110,8 +115,16 @@ fn execute<R: io::Read>(mut reader: R) -> Result<()> {
 fn repl() -> Result<()> {
     let history = History::new()?;
     let mut interpreter = Interpreter::new()?;
+    if let Err(e) = interpreter.enable_job_control() {
+        print_error(&e.context("failed enabling job control").into());
+    }
 
-    while let Some(line) = history.readline(&format!("{} $ ", interpreter.cwd()))? {
+    loop {
+        interpreter.update_jobs();
+        let line = match history.readline(&format!("{} $ ", interpreter.cwd()))? {
+            Some(line) => line,
+            None => break,
+        };
         if let Err(e) = parser::parse(&line).and_then(|stmts| interpreter.execute(&stmts)) {
             print_error(&e);
         }
@@ -0,0 +1,97 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::ops::Deref;
+
+use crate::glob::{Glob, NoMatch};
+use crate::status::Status;
+
+/// Shell options, toggled with `set -o NAME` and `set +o NAME`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    /// Unmatched patterns expand to nothing.
+    pub nullglob: bool,
+    /// Unmatched patterns are an expansion error. Takes precedence over
+    /// nullglob.
+    pub failglob: bool,
+    /// Patterns match hidden files without an explicit leading `.`.
+    pub dotglob: bool,
+    /// `>` refuses to overwrite existing files. `>|` still does.
+    pub noclobber: bool,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, argv: &[Cow<OsStr>]) -> Status {
+        if argv.is_empty() || (argv.len() == 1 && argv[0].deref() == "-o") {
+            for (name, value) in self.iter() {
+                println!("{:<10}{}", name, if value { "on" } else { "off" });
+            }
+            return Status::Success;
+        }
+
+        let mut status = Status::Success;
+        let mut args = argv.iter();
+        while let Some(flag) = args.next() {
+            let enable = match flag.to_str() {
+                Some("-o") => true,
+                Some("+o") => false,
+                _ => {
+                    display!("set: unknown flag: {}", flag.to_string_lossy());
+                    return Status::Failure;
+                }
+            };
+
+            let name = match args.next() {
+                Some(name) => name,
+                None => {
+                    display!("set: {}: option name required", flag.to_string_lossy());
+                    return Status::Failure;
+                }
+            };
+
+            match self.get_mut(name) {
+                Some(option) => *option = enable,
+                None => {
+                    display!("set: unknown option: {}", name.to_string_lossy());
+                    status = Status::Failure;
+                }
+            }
+        }
+        status
+    }
+
+    /// How pathname expansion should behave under these options.
+    pub fn glob(&self) -> Glob {
+        let no_match = if self.failglob {
+            NoMatch::Error
+        } else if self.nullglob {
+            NoMatch::Empty
+        } else {
+            NoMatch::Literal
+        };
+        Glob::new().no_match(no_match).hidden(self.dotglob)
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&'static str, bool)> {
+        vec![
+            ("dotglob", self.dotglob),
+            ("failglob", self.failglob),
+            ("noclobber", self.noclobber),
+            ("nullglob", self.nullglob),
+        ]
+        .into_iter()
+    }
+
+    fn get_mut(&mut self, name: &OsStr) -> Option<&mut bool> {
+        match name.to_str()? {
+            "dotglob" => Some(&mut self.dotglob),
+            "failglob" => Some(&mut self.failglob),
+            "noclobber" => Some(&mut self.noclobber),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
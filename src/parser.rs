@@ -1,6 +1,12 @@
-use crate::ast::{Block, Exportable, IfStmt, Program, Stmt, WhileStmt};
+use std::iter;
+
+use crate::ast::{
+    AndOrList, Block, Exportable, ForStmt, FunctionStmt, GroupStmt, IfStmt, LogicalOp, MatchArm,
+    MatchStmt, Program, Stmt, WhileStmt,
+};
 use crate::command::Command;
 use crate::lexer::{Kind, Lexer, Token};
+use crate::redirect::Redirect;
 use crate::word::Word;
 use crate::Result;
 
@@ -10,7 +16,8 @@ pub fn parse(input: &[u8]) -> Result<Program> {
 
 struct Parser<'input> {
     lexer: Lexer<'input>,
-    peek: Option<Token>,
+    /// Tokens that were looked at and put back, the next one last.
+    peeked: Vec<Token>,
 }
 
 macro_rules! expected {
@@ -27,12 +34,12 @@ impl<'input> Parser<'input> {
     fn new(src: &'input [u8]) -> Self {
         Self {
             lexer: Lexer::new(src),
-            peek: None,
+            peeked: Vec::new(),
         }
     }
 
     fn next_token(&mut self) -> Result<Option<Token>> {
-        match self.peek.take() {
+        match self.peeked.pop() {
             Some(token) => Ok(Some(token)),
             None => match self.lexer.next() {
                 Some(Ok(token)) => Ok(Some(token)),
@@ -42,9 +49,15 @@ impl<'input> Parser<'input> {
         }
     }
 
+    fn expect_token(&mut self, expected: &str) -> Result<Token> {
+        match self.next_token()? {
+            Some(token) => Ok(token),
+            None => expected!(expected, Option::<Token>::None),
+        }
+    }
+
     fn push_token(&mut self, token: Token) {
-        assert!(self.peek.is_none());
-        self.peek = Some(token);
+        self.peeked.push(token);
     }
 
     fn match_token(&mut self, expected: &Kind) -> Result<bool> {
@@ -96,10 +109,13 @@ impl<'input> Parser<'input> {
 
     fn parse_block(&mut self) -> Result<Block> {
         self.assert_token(&Kind::LeftBrace)?;
+        self.parse_stmts_until(&Kind::RightBrace)
+    }
 
+    fn parse_stmts_until(&mut self, end: &Kind) -> Result<Block> {
         let mut block = Vec::new();
         while let Some(token) = self.next_token()? {
-            if token.kind == Kind::RightBrace {
+            if token.kind == *end {
                 return Ok(block);
             }
 
@@ -110,21 +126,115 @@ impl<'input> Parser<'input> {
         bail!("unexpected EOF parsing block");
     }
 
+    /// Parses a statement, which is an and-or list of pipelines that may run
+    /// in the background, as in `cat hosts | while read host { ... } && echo ok`.
     fn parse_stmt(&mut self, token: Token) -> Result<Stmt> {
+        let list = self.parse_and_or_list(token)?;
+        Ok(if self.match_token(&Kind::Background)? {
+            let mut stmts = iter::once(&*list.first).chain(list.rest.iter().map(|(_, stmt)| stmt));
+            if !stmts
+                .all(|stmt| matches!(stmt, Stmt::Command(_) | Stmt::Group(_) | Stmt::Subshell(_)))
+            {
+                bail!("only commands, groups and subshells can run in the background");
+            }
+            Stmt::Background(list)
+        } else if list.rest.is_empty() {
+            *list.first
+        } else {
+            Stmt::AndOr(list)
+        })
+    }
+
+    fn parse_and_or_list(&mut self, token: Token) -> Result<AndOrList> {
+        let first = self.parse_pipeline(token)?;
+        let mut rest = Vec::new();
+        loop {
+            let op = if self.match_token(&Kind::And)? {
+                LogicalOp::And
+            } else if self.match_token(&Kind::Or)? {
+                LogicalOp::Or
+            } else {
+                break;
+            };
+            let token = self.expect_token("command")?;
+            rest.push((op, self.parse_pipeline(token)?));
+        }
+        Ok(AndOrList::new(first, rest))
+    }
+
+    /// Parses statements joined by `|`. Pipelines of plain commands are
+    /// chained together by `parse_command` instead.
+    fn parse_pipeline(&mut self, token: Token) -> Result<Stmt> {
+        let first = self.parse_pipeline_element(token)?;
+        if !self.match_token(&Kind::Pipe)? {
+            return Ok(first);
+        }
+        if let Stmt::Command(ref command) = first {
+            if command.is_negated() {
+                bail!("'!' can't negate a pipeline with compound statements");
+            }
+        }
+
+        let mut stmts = vec![first];
+        loop {
+            let token = self.expect_token("statement")?;
+            stmts.push(self.parse_pipeline_element(token)?);
+            if !self.match_token(&Kind::Pipe)? {
+                break;
+            }
+        }
+        Ok(Stmt::Pipeline(stmts))
+    }
+
+    fn parse_pipeline_element(&mut self, token: Token) -> Result<Stmt> {
+        match token.kind {
+            Kind::LeftBrace => {
+                let body = self.parse_stmts_until(&Kind::RightBrace)?;
+                return Ok(Stmt::Group(GroupStmt::new(body, self.parse_redirects()?)));
+            }
+            Kind::LeftParen => {
+                let body = self.parse_stmts_until(&Kind::RightParen)?;
+                return Ok(Stmt::Subshell(GroupStmt::new(
+                    body,
+                    self.parse_redirects()?,
+                )));
+            }
+            _ => {}
+        }
+
         let word = assert_word(token, "statement")?;
         Ok(match word.as_bytes() {
-            b"if" => Stmt::If(self.parse_if_stmt()?),
-            b"while" => Stmt::While(self.parse_while_stmt()?),
+            b"if" => {
+                // Redirects after an `else if` chain apply to all of it.
+                let mut stmt = self.parse_if_stmt()?;
+                stmt.redirects = self.parse_redirects()?;
+                Stmt::If(stmt)
+            }
+            b"while" | b"until" => {
+                let mut stmt = self.parse_while_stmt()?;
+                stmt.redirects = self.parse_redirects()?;
+                if word.as_bytes() == b"while" {
+                    Stmt::While(stmt)
+                } else {
+                    Stmt::Until(stmt)
+                }
+            }
+            b"for" => Stmt::For(self.parse_for_stmt()?),
+            b"match" => Stmt::Match(self.parse_match_stmt()?),
+            b"fn" => Stmt::Function(self.parse_function_stmt()?),
+            b"break" => Stmt::Break,
+            b"continue" => Stmt::Continue,
+            b"return" => Stmt::Return(self.match_word()?),
             b"export" => Stmt::Export(self.parse_export_stmt()?),
             _ => self.parse_assignment_or_command(word)?,
         })
     }
 
     fn parse_if_stmt(&mut self) -> Result<IfStmt> {
-        let test = self.parse_command(None)?;
+        let test = self.parse_test()?;
         let consequent = self.parse_block()?;
 
-        let alternate = if self.match_token(&Kind::Word("else".into()))? {
+        let alternate = if self.match_else()? {
             Some(if self.match_token(&Kind::Word("if".into()))? {
                 vec![Stmt::If(self.parse_if_stmt()?)]
             } else {
@@ -137,12 +247,81 @@ impl<'input> Parser<'input> {
         Ok(IfStmt::new(test, consequent, alternate))
     }
 
+    /// Matches an `else`, which may also start the line after the `}` before
+    /// it, in which case the lexer has ended the statement in between.
+    fn match_else(&mut self) -> Result<bool> {
+        let keyword = Kind::Word("else".into());
+        if self.match_token(&keyword)? {
+            return Ok(true);
+        }
+        if let Some(token) = self.next_token()? {
+            if token.kind == Kind::Semi && self.match_token(&keyword)? {
+                return Ok(true);
+            }
+            self.push_token(token);
+        }
+        Ok(false)
+    }
+
     fn parse_while_stmt(&mut self) -> Result<WhileStmt> {
-        let test = self.parse_command(None)?;
+        let test = self.parse_test()?;
         let body = self.parse_block()?;
         Ok(WhileStmt::new(test, body))
     }
 
+    fn parse_for_stmt(&mut self) -> Result<ForStmt> {
+        let name = assert_word(self.next_token()?, "loop variable name")?;
+        if name.quote.is_some() || !name.is_valid_name() {
+            bail!("not a valid name: {}", name);
+        }
+        self.assert_token(&Kind::Word("in".into()))?;
+
+        let mut words = Vec::new();
+        while let Some(word) = self.match_word()? {
+            words.push(word);
+        }
+
+        let body = self.parse_block()?;
+        Ok(ForStmt::new(name, words, body))
+    }
+
+    fn parse_match_stmt(&mut self) -> Result<MatchStmt> {
+        let word = assert_word(self.next_token()?, "word to match")?;
+        self.assert_token(&Kind::LeftBrace)?;
+
+        let mut arms = Vec::new();
+        loop {
+            let token = self.next_token()?;
+            match token {
+                Some(Token {
+                    kind: Kind::RightBrace,
+                    ..
+                }) => break,
+                // Arms on separate lines are separated by semis.
+                Some(Token {
+                    kind: Kind::Semi, ..
+                }) => continue,
+                token => {
+                    let mut patterns = vec![assert_word(token, "pattern")?];
+                    while self.match_token(&Kind::Pipe)? {
+                        patterns.push(assert_word(self.next_token()?, "pattern")?);
+                    }
+                    arms.push(MatchArm::new(patterns, self.parse_block()?));
+                }
+            }
+        }
+        Ok(MatchStmt::new(word, arms))
+    }
+
+    fn parse_function_stmt(&mut self) -> Result<FunctionStmt> {
+        let name = assert_word(self.next_token()?, "function name")?;
+        if name.quote.is_some() || !name.is_valid_name() {
+            bail!("not a valid function name: {}", name);
+        }
+        let body = self.parse_block()?;
+        Ok(FunctionStmt::new(name, body))
+    }
+
     fn parse_export_stmt(&mut self) -> Result<Vec<Exportable>> {
         let mut exports = Vec::new();
         while let Some(word) = self.match_word()? {
@@ -162,37 +341,92 @@ impl<'input> Parser<'input> {
         }
     }
 
+    /// Parses a command along with any assignments and `!` before its name,
+    /// as in `! PORT=8000 ./server`, or just the assignments without one.
     fn parse_assignment_or_command(&mut self, word: Word) -> Result<Stmt> {
+        if word == Word::unquoted("!") {
+            let word = assert_word(self.next_token()?, "command")?;
+            if word == Word::unquoted("!") {
+                bail!("unexpected '!' after '!'");
+            }
+            return match self.parse_assignment_or_command(word)? {
+                Stmt::Command(mut command) => {
+                    command.negate();
+                    Ok(Stmt::Command(command))
+                }
+                _ => bail!("expected command after '!'"),
+            };
+        }
+
         if let Some(pair) = word.parse_name_value_pair() {
             let mut env = vec![pair];
             while let Some(word) = self.match_word()? {
                 if let Some(pair) = word.parse_name_value_pair() {
                     env.push(pair);
                 } else {
-                    let mut command = self.parse_command(Some(word))?;
+                    let mut command = self.parse_command(word)?;
                     command.set_env(env);
                     return Ok(Stmt::Command(command));
                 }
             }
             Ok(Stmt::Assignment(env))
         } else {
-            Ok(Stmt::Command(self.parse_command(Some(word))?))
+            Ok(Stmt::Command(self.parse_command(word)?))
         }
     }
 
-    fn parse_command(&mut self, mut name: Option<Word>) -> Result<Command> {
-        let name = match name.take() {
-            Some(name) => name,
-            None => assert_word(self.next_token()?, "command")?,
-        };
+    fn peek_compound(&mut self) -> Result<bool> {
+        Ok(match self.next_token()? {
+            Some(token) => {
+                let compound = is_compound(&token.kind);
+                self.push_token(token);
+                compound
+            }
+            None => false,
+        })
+    }
+
+    fn parse_redirects(&mut self) -> Result<Vec<Redirect<Word>>> {
+        let mut redirects = Vec::new();
+        while let Some(token) = self.next_token()? {
+            match token.kind {
+                Kind::Redirect(redirect) => redirects.push(redirect),
+                _ => {
+                    self.push_token(token);
+                    break;
+                }
+            }
+        }
+        Ok(redirects)
+    }
+
+    fn parse_test(&mut self) -> Result<AndOrList> {
+        let token = self.expect_token("command")?;
+        self.parse_and_or_list(token)
+    }
+
+    fn parse_command(&mut self, name: Word) -> Result<Command> {
         let mut command = Command::from_name(name);
 
         while let Some(token) = self.next_token()? {
             match token.kind {
                 Kind::Word(arg) => command.add_argument(arg),
                 Kind::Redirect(redirect) => command.add_redirect(redirect),
+                Kind::Pipe if self.peek_compound()? => {
+                    // Leave the pipe for `parse_pipeline`, since only statements
+                    // can be piped into a compound statement.
+                    self.push_token(token);
+                    break;
+                }
                 Kind::Pipe => {
-                    command.set_pipeline(self.parse_command(None)?);
+                    let word = assert_word(self.next_token()?, "command")?;
+                    if word == Word::unquoted("!") {
+                        bail!("'!' is only allowed at the start of a pipeline");
+                    }
+                    match self.parse_assignment_or_command(word)? {
+                        Stmt::Command(pipeline) => command.set_pipeline(pipeline),
+                        _ => bail!("expected command after '|'"),
+                    }
                     break;
                 }
                 _ => {
@@ -206,6 +440,18 @@ impl<'input> Parser<'input> {
     }
 }
 
+/// Whether a token starts a compound statement rather than a command.
+fn is_compound(kind: &Kind) -> bool {
+    match *kind {
+        Kind::LeftBrace | Kind::LeftParen => true,
+        Kind::Word(ref word) => matches!(
+            word.as_bytes(),
+            b"if" | b"while" | b"until" | b"for" | b"match"
+        ),
+        _ => false,
+    }
+}
+
 fn assert_word<T>(token: T, expected: &str) -> Result<Word>
 where
     T: Into<Option<Token>>,
@@ -223,6 +469,8 @@ where
 mod tests {
     use super::*;
     use crate::ast::NameValuePair;
+    use crate::redirect::{Target, WriteMode};
+    use crate::word::Quote;
 
     #[test]
     fn simple() {
@@ -358,6 +606,293 @@ if /bin/a {
         );
     }
 
+    #[test]
+    fn else_on_next_line() {
+        let src = br#"
+if a {
+  echo a
+}
+else if b {
+  echo b
+}
+
+# Blank lines and comments don't get in the way.
+else {
+  echo c
+}
+if d { }
+echo d
+"#;
+        assert_eq!(
+            parse(src).unwrap(),
+            vec![
+                Stmt::If(IfStmt::new(
+                    Command::from_name("a".into()),
+                    vec![Stmt::Command(Command::new("echo".into(), vec!["a".into()]))],
+                    Some(vec![Stmt::If(IfStmt::new(
+                        Command::from_name("b".into()),
+                        vec![Stmt::Command(Command::new("echo".into(), vec!["b".into()]))],
+                        Some(vec![Stmt::Command(Command::new(
+                            "echo".into(),
+                            vec!["c".into()]
+                        ))]),
+                    ))]),
+                )),
+                Stmt::If(IfStmt::new(
+                    Command::from_name("d".into()),
+                    Vec::new(),
+                    None
+                )),
+                Stmt::Command(Command::new("echo".into(), vec!["d".into()])),
+            ],
+        );
+        assert!(parse(b"if a { }\n\n").is_ok());
+        assert!(parse(b"else { }\n").is_err());
+    }
+
+    #[test]
+    fn until_stmt() {
+        assert_eq!(
+            parse(b"until test -e ready {\n  sleep 1\n} >log\n").unwrap(),
+            vec![Stmt::Until({
+                let mut stmt = WhileStmt::new(
+                    Command::new("test".into(), vec!["-e".into(), "ready".into()]),
+                    vec![Stmt::Command(Command::new(
+                        "sleep".into(),
+                        vec!["1".into()],
+                    ))],
+                );
+                stmt.redirects = vec![Redirect::new(
+                    1,
+                    Target::Output("log".into(), WriteMode::Truncate),
+                )];
+                stmt
+            })],
+        );
+    }
+
+    #[test]
+    fn for_stmt() {
+        assert_eq!(
+            parse(b"for host in a.example.com b.example.com {\n  ssh $host uptime\n}\n").unwrap(),
+            vec![Stmt::For(ForStmt::new(
+                "host".into(),
+                vec!["a.example.com".into(), "b.example.com".into()],
+                vec![Stmt::Command(Command::new(
+                    "ssh".into(),
+                    vec!["$host".into(), "uptime".into()],
+                ))],
+            ))],
+        );
+    }
+
+    #[test]
+    fn for_stmt_missing_in() {
+        assert!(parse(b"for host a b { echo $host }\n").is_err());
+    }
+
+    #[test]
+    fn function_stmt() {
+        assert_eq!(
+            parse(b"fn greet {\n  echo hello $1\n}\ngreet world\n").unwrap(),
+            vec![
+                Stmt::Function(FunctionStmt::new(
+                    "greet".into(),
+                    vec![Stmt::Command(Command::new(
+                        "echo".into(),
+                        vec!["hello".into(), "$1".into()],
+                    ))],
+                )),
+                Stmt::Command(Command::new("greet".into(), vec!["world".into()])),
+            ],
+        );
+    }
+
+    #[test]
+    fn control_flow_stmts() {
+        let src = br#"
+while true {
+  if a { break }
+  if b { continue }
+}
+fn f { return }
+fn g { return 3 }
+"#;
+        assert_eq!(
+            parse(src).unwrap(),
+            vec![
+                Stmt::While(WhileStmt::new(
+                    Command::from_name("true".into()),
+                    vec![
+                        Stmt::If(IfStmt::new(
+                            Command::from_name("a".into()),
+                            vec![Stmt::Break],
+                            None,
+                        )),
+                        Stmt::If(IfStmt::new(
+                            Command::from_name("b".into()),
+                            vec![Stmt::Continue],
+                            None,
+                        )),
+                    ],
+                )),
+                Stmt::Function(FunctionStmt::new("f".into(), vec![Stmt::Return(None)])),
+                Stmt::Function(FunctionStmt::new(
+                    "g".into(),
+                    vec![Stmt::Return(Some("3".into()))],
+                )),
+            ],
+        );
+    }
+
+    #[test]
+    fn function_stmt_invalid_name() {
+        assert!(parse(b"fn 'greet' { echo hello }\n").is_err());
+        assert!(parse(b"fn a/b { echo hello }\n").is_err());
+    }
+
+    #[test]
+    fn and_or_list() {
+        let mut build = Command::from_name("cargo".into());
+        build.add_argument("build".into());
+        build.set_pipeline(Command::new("tee".into(), vec!["log".into()]));
+        assert_eq!(
+            parse(b"cargo build | tee log && ./run || echo failed\n").unwrap(),
+            vec![Stmt::AndOr(AndOrList::new(
+                build,
+                vec![
+                    (
+                        LogicalOp::And,
+                        Stmt::Command(Command::from_name("./run".into()))
+                    ),
+                    (
+                        LogicalOp::Or,
+                        Stmt::Command(Command::new("echo".into(), vec!["failed".into()]))
+                    ),
+                ],
+            ))],
+        );
+    }
+
+    #[test]
+    fn and_or_list_test() {
+        assert_eq!(
+            parse(b"while a || b { c }\n").unwrap(),
+            vec![Stmt::While(WhileStmt::new(
+                AndOrList::new(
+                    Command::from_name("a".into()),
+                    vec![(LogicalOp::Or, Stmt::Command(Command::from_name("b".into())))],
+                ),
+                vec![Stmt::Command(Command::from_name("c".into()))],
+            ))],
+        );
+    }
+
+    #[test]
+    fn and_or_list_of_pipelines() {
+        let read = WhileStmt::new(
+            Command::new("read".into(), vec!["l".into()]),
+            vec![Stmt::Command(Command::new(
+                "echo".into(),
+                vec!["$l".into()],
+            ))],
+        );
+        assert_eq!(
+            parse(b"seq 3 | while read l { echo $l } && echo ok\na || b | while c { d }\n")
+                .unwrap(),
+            vec![
+                Stmt::AndOr(AndOrList::new(
+                    Stmt::Pipeline(vec![
+                        Stmt::Command(Command::new("seq".into(), vec!["3".into()])),
+                        Stmt::While(read),
+                    ]),
+                    vec![(
+                        LogicalOp::And,
+                        Stmt::Command(Command::new("echo".into(), vec!["ok".into()]))
+                    )],
+                )),
+                Stmt::AndOr(AndOrList::new(
+                    Command::from_name("a".into()),
+                    vec![(
+                        LogicalOp::Or,
+                        Stmt::Pipeline(vec![
+                            Stmt::Command(Command::from_name("b".into())),
+                            Stmt::While(WhileStmt::new(
+                                Command::from_name("c".into()),
+                                vec![Stmt::Command(Command::from_name("d".into()))],
+                            )),
+                        ])
+                    )],
+                )),
+            ],
+        );
+    }
+
+    #[test]
+    fn and_or_list_missing_command() {
+        assert!(parse(b"make &&\n").is_err());
+    }
+
+    #[test]
+    fn negated_command() {
+        let mut grep = Command::new("grep".into(), vec!["-q".into(), "foo".into()]);
+        grep.set_pipeline(Command::from_name("wc".into()));
+        grep.negate();
+        assert_eq!(
+            parse(b"if ! grep -q foo | wc { echo missing }\n").unwrap(),
+            vec![Stmt::If(IfStmt::new(
+                grep,
+                vec![Stmt::Command(Command::new(
+                    "echo".into(),
+                    vec!["missing".into()]
+                ))],
+                None,
+            ))],
+        );
+    }
+
+    #[test]
+    fn negated_command_with_assignment() {
+        let env = || {
+            vec![NameValuePair::new(
+                Word::unquoted("FOO"),
+                Word::unquoted("1"),
+            )]
+        };
+        let mut command = Command::from_name("/usr/bin/env".into());
+        command.set_env(env());
+        let mut grep = Command::new("grep".into(), vec!["x".into()]);
+        grep.set_env(env());
+        command.set_pipeline(grep);
+        command.negate();
+        assert_eq!(
+            parse(b"! FOO=1 /usr/bin/env | FOO=1 grep x\n").unwrap(),
+            vec![Stmt::Command(command)],
+        );
+        assert!(parse(b"! FOO=1\n").is_err());
+        assert!(parse(b"a | FOO=1\n").is_err());
+    }
+
+    #[test]
+    fn negated_command_invalid() {
+        assert!(parse(b"!\n").is_err());
+        assert!(parse(b"! ! true\n").is_err());
+        assert!(parse(b"true | ! false\n").is_err());
+        assert!(parse(b"! echo a | while read l { true } && echo X\n").is_err());
+        assert!(parse(b"! a | b | { c }\n").is_err());
+    }
+
+    #[test]
+    fn quoted_bang_is_a_command() {
+        assert_eq!(
+            parse(b"'!' x\n").unwrap(),
+            vec![Stmt::Command(Command::new(
+                Word::new("!", Quote::Single),
+                vec!["x".into()]
+            ))],
+        );
+    }
+
     #[test]
     fn pipeline() {
         let mut cmd = Command::new("echo".into(), vec!["Hello".into(), "world".into()]);
@@ -367,4 +902,209 @@ if /bin/a {
             vec![Stmt::Command(cmd)],
         );
     }
+
+    #[test]
+    fn background() {
+        assert_eq!(
+            parse(b"sleep 10 &\nmake && make install & echo started\n").unwrap(),
+            vec![
+                Stmt::Background(Command::new("sleep".into(), vec!["10".into()]).into()),
+                Stmt::Background(AndOrList::new(
+                    Command::from_name("make".into()),
+                    vec![(
+                        LogicalOp::And,
+                        Stmt::Command(Command::new("make".into(), vec!["install".into()]))
+                    )],
+                )),
+                Stmt::Command(Command::new("echo".into(), vec!["started".into()])),
+            ],
+        );
+    }
+
+    #[test]
+    fn background_invalid() {
+        assert!(parse(b"&\n").is_err());
+        assert!(parse(b"x=1 &\n").is_err());
+        assert!(parse(b"if a { b } &\n").is_err());
+    }
+
+    #[test]
+    fn group_stmt() {
+        assert_eq!(
+            parse(b"{ make; make test } >build.log 2>&1\n{}\n").unwrap(),
+            vec![
+                Stmt::Group(GroupStmt::new(
+                    vec![
+                        Stmt::Command(Command::from_name("make".into())),
+                        Stmt::Command(Command::new("make".into(), vec!["test".into()])),
+                    ],
+                    vec![
+                        Redirect::new(1, Target::Output("build.log".into(), WriteMode::Truncate)),
+                        Redirect::new(2, Target::Fd(1)),
+                    ],
+                )),
+                Stmt::Group(GroupStmt::new(Vec::new(), Vec::new())),
+            ],
+        );
+    }
+
+    #[test]
+    fn subshell_stmt() {
+        assert_eq!(
+            parse(b"(cd src\nmake)\n( )\n").unwrap(),
+            vec![
+                Stmt::Subshell(GroupStmt::new(
+                    vec![
+                        Stmt::Command(Command::new("cd".into(), vec!["src".into()])),
+                        Stmt::Command(Command::from_name("make".into())),
+                    ],
+                    Vec::new(),
+                )),
+                Stmt::Subshell(GroupStmt::new(Vec::new(), Vec::new())),
+            ],
+        );
+        assert!(parse(b"(echo a\n").is_err());
+        assert!(parse(b"(echo a }\n").is_err());
+    }
+
+    #[test]
+    fn group_and_subshell_in_and_or_list() {
+        let make = GroupStmt::new(
+            vec![Stmt::AndOr(AndOrList::new(
+                Command::new("cd".into(), vec!["dir".into()]),
+                vec![(
+                    LogicalOp::And,
+                    Stmt::Command(Command::from_name("make".into())),
+                )],
+            ))],
+            Vec::new(),
+        );
+        assert_eq!(
+            parse(b"(exit 3) && echo ok\n( cd dir && make ) || exit 1\na || { b; c } >log &\n")
+                .unwrap(),
+            vec![
+                Stmt::AndOr(AndOrList::new(
+                    Stmt::Subshell(GroupStmt::new(
+                        vec![Stmt::Command(Command::new("exit".into(), vec!["3".into()]))],
+                        Vec::new(),
+                    )),
+                    vec![(
+                        LogicalOp::And,
+                        Stmt::Command(Command::new("echo".into(), vec!["ok".into()]))
+                    )],
+                )),
+                Stmt::AndOr(AndOrList::new(
+                    Stmt::Subshell(make),
+                    vec![(
+                        LogicalOp::Or,
+                        Stmt::Command(Command::new("exit".into(), vec!["1".into()]))
+                    )],
+                )),
+                Stmt::Background(AndOrList::new(
+                    Command::from_name("a".into()),
+                    vec![(
+                        LogicalOp::Or,
+                        Stmt::Group(GroupStmt::new(
+                            vec![
+                                Stmt::Command(Command::from_name("b".into())),
+                                Stmt::Command(Command::from_name("c".into())),
+                            ],
+                            vec![Redirect::new(
+                                1,
+                                Target::Output("log".into(), WriteMode::Truncate)
+                            )],
+                        ))
+                    )],
+                )),
+            ],
+        );
+        assert!(parse(b"a && (b\n").is_err());
+    }
+
+    #[test]
+    fn match_stmt() {
+        assert_eq!(
+            parse(b"match $1 {\n  start | 'restart' { a }\n  * {}\n}\nmatch x { y { b } }\n")
+                .unwrap(),
+            vec![
+                Stmt::Match(MatchStmt::new(
+                    "$1".into(),
+                    vec![
+                        MatchArm::new(
+                            vec!["start".into(), Word::new("restart", Quote::Single)],
+                            vec![Stmt::Command(Command::from_name("a".into()))],
+                        ),
+                        MatchArm::new(vec!["*".into()], Vec::new()),
+                    ],
+                )),
+                Stmt::Match(MatchStmt::new(
+                    "x".into(),
+                    vec![MatchArm::new(
+                        vec!["y".into()],
+                        vec![Stmt::Command(Command::from_name("b".into()))],
+                    )],
+                )),
+            ],
+        );
+        assert!(parse(b"match x\n").is_err());
+        assert!(parse(b"match x { y }\n").is_err());
+        assert!(parse(b"match x { y | { a } }\n").is_err());
+    }
+
+    #[test]
+    fn if_while_redirects() {
+        let mut chain = IfStmt::new(
+            Command::from_name("a".into()),
+            Vec::new(),
+            Some(vec![Stmt::If(IfStmt::new(
+                Command::from_name("b".into()),
+                Vec::new(),
+                None,
+            ))]),
+        );
+        chain.redirects = vec![
+            Redirect::new(1, Target::Output("report".into(), WriteMode::Truncate)),
+            Redirect::new(2, Target::Fd(1)),
+        ];
+        let mut read = WhileStmt::new(
+            Command::new("read".into(), vec!["l".into()]),
+            vec![Stmt::Command(Command::from_name("x".into()))],
+        );
+        read.redirects = vec![Redirect::new(0, Target::Input("hosts".into()))];
+        assert_eq!(
+            parse(b"if a { } else if b { } >report 2>&1\nwhile read l { x } <hosts\n").unwrap(),
+            vec![Stmt::If(chain), Stmt::While(read)],
+        );
+    }
+
+    #[test]
+    fn pipeline_stmt() {
+        let mut grep = Command::new("grep".into(), vec!["x".into()]);
+        grep.set_pipeline(Command::from_name("wc".into()));
+        assert_eq!(
+            parse(b"cat f | while read l { echo $l }\nif a { b } | grep x | wc\n").unwrap(),
+            vec![
+                Stmt::Pipeline(vec![
+                    Stmt::Command(Command::new("cat".into(), vec!["f".into()])),
+                    Stmt::While(WhileStmt::new(
+                        Command::new("read".into(), vec!["l".into()]),
+                        vec![Stmt::Command(Command::new(
+                            "echo".into(),
+                            vec!["$l".into()]
+                        ))],
+                    )),
+                ]),
+                Stmt::Pipeline(vec![
+                    Stmt::If(IfStmt::new(
+                        Command::from_name("a".into()),
+                        vec![Stmt::Command(Command::from_name("b".into()))],
+                        None,
+                    )),
+                    Stmt::Command(grep),
+                ]),
+            ],
+        );
+        assert!(parse(b"{ a } |\n").is_err());
+        assert!(parse(b"{ a } | b &\n").is_err());
+    }
 }
@@ -1,29 +1,133 @@
+use std::env;
+use std::ffi::OsStr;
 use std::fmt;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
 use std::path::Path;
+use std::process;
 
 use failure::ResultExt;
+use nix::unistd;
 
 use crate::word::Word;
 use crate::Result;
 
+/// Redirects a file descriptor of a command, as in `2>err.log` or `3<&0`.
 #[derive(Clone, Debug, PartialEq)]
-pub enum Redirect<P> {
-    InFile(P),
-    OutErr,
-    OutFile(P, WriteMode),
-    ErrOut,
-    ErrFile(P, WriteMode),
+pub struct Redirect<P> {
+    pub fd: RawFd,
+    pub target: Target<P>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target<P> {
+    /// `<file`
+    Input(P),
+    /// `>file` or `>>file`
+    Output(P, WriteMode),
+    /// `<>file`, opened for reading and writing.
+    ReadWrite(P),
+    /// `>&N` or `<&N`, duplicating another file descriptor.
+    Fd(RawFd),
+    /// `>&-` or `<&-`
+    Close,
+    /// The body of a `<<TAG` here-document.
+    HereDoc(P),
+    /// The word of a `<<< word` here-string, fed with a trailing newline.
+    HereString(P),
+}
+
+impl<P> Redirect<P> {
+    pub fn new(fd: RawFd, target: Target<P>) -> Self {
+        Self { fd, target }
+    }
+
+    /// Converts the path or word of the target, keeping everything else.
+    pub fn try_map<'a, Q, F>(&'a self, f: F) -> Result<Redirect<Q>>
+    where
+        F: FnOnce(&'a P) -> Result<Q>,
+    {
+        let target = match self.target {
+            Target::Input(ref path) => Target::Input(f(path)?),
+            Target::Output(ref path, mode) => Target::Output(f(path)?, mode),
+            Target::ReadWrite(ref path) => Target::ReadWrite(f(path)?),
+            Target::Fd(fd) => Target::Fd(fd),
+            Target::Close => Target::Close,
+            Target::HereDoc(ref body) => Target::HereDoc(f(body)?),
+            Target::HereString(ref word) => Target::HereString(f(word)?),
+        };
+        Ok(Redirect::new(self.fd, target))
+    }
+}
+
+impl<P: AsRef<OsStr>> Redirect<P> {
+    /// Applies the redirect to this process's file descriptors. With
+    /// `noclobber`, `>` refuses to overwrite an existing regular file.
+    pub fn apply(&self, noclobber: bool) -> Result<()> {
+        let file = match self.target {
+            Target::Input(ref path) => {
+                let path = Path::new(path.as_ref());
+                File::open(path).with_context(|_| path.display().to_string())?
+            }
+            Target::Output(ref path, mode) => mode.open(Path::new(path.as_ref()), noclobber)?,
+            Target::ReadWrite(ref path) => {
+                let path = Path::new(path.as_ref());
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(path)
+                    .with_context(|_| path.display().to_string())?
+            }
+            Target::Fd(fd) => {
+                unistd::dup2(fd, self.fd).with_context(|_| fd.to_string())?;
+                return Ok(());
+            }
+            Target::Close => {
+                let _ = unistd::close(self.fd);
+                return Ok(());
+            }
+            Target::HereDoc(ref body) => here_document(body.as_ref().as_bytes())?,
+            Target::HereString(ref word) => {
+                let mut contents = word.as_ref().as_bytes().to_vec();
+                contents.push(b'\n');
+                here_document(&contents)?
+            }
+        };
+
+        if file.as_raw_fd() == self.fd {
+            // Opened right where it belongs, so keep it open.
+            let _ = file.into_raw_fd();
+        } else {
+            unistd::dup2(file.as_raw_fd(), self.fd)?;
+        }
+        Ok(())
+    }
 }
 
 impl fmt::Display for Redirect<Word> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            Redirect::InFile(ref path) => write!(f, "<{}", path),
-            Redirect::OutErr => write!(f, ">&2"),
-            Redirect::OutFile(ref path, mode) => write!(f, "{}{}", mode, path),
-            Redirect::ErrOut => write!(f, "2>&1"),
-            Redirect::ErrFile(ref path, mode) => write!(f, "2{}{}", mode, path),
+        // Leave out the file descriptor when it's the operator's default.
+        let fd = |default| {
+            if self.fd == default {
+                String::new()
+            } else {
+                self.fd.to_string()
+            }
+        };
+        match self.target {
+            Target::Input(ref path) => write!(f, "{}<{}", fd(0), path),
+            Target::Output(ref path, mode) => write!(f, "{}{}{}", fd(1), mode, path),
+            Target::ReadWrite(ref path) => write!(f, "{}<>{}", fd(0), path),
+            Target::Fd(other) if self.fd == 0 => write!(f, "<&{}", other),
+            Target::Fd(other) => write!(f, "{}>&{}", fd(1), other),
+            Target::Close if self.fd == 0 => write!(f, "<&-"),
+            Target::Close => write!(f, "{}>&-", fd(1)),
+            Target::HereDoc(_) => write!(f, "{}<<here-document", fd(0)),
+            Target::HereString(ref word) => write!(f, "{}<<<{}", fd(0), word),
         }
     }
 }
@@ -32,15 +136,28 @@ impl fmt::Display for Redirect<Word> {
 pub enum WriteMode {
     Truncate,
     Append,
+    /// `>|`, which truncates even when noclobber is set.
+    Clobber,
 }
 
 impl WriteMode {
-    pub fn open<P: AsRef<Path>>(self, path: P) -> Result<File> {
+    pub fn open<P: AsRef<Path>>(self, path: P, noclobber: bool) -> Result<File> {
+        let path = path.as_ref();
         let mut file = OpenOptions::new();
         file.write(true).create(true);
 
         match self {
-            WriteMode::Truncate => {
+            WriteMode::Truncate if noclobber => match fs::metadata(path) {
+                Ok(ref metadata) if metadata.is_file() => {
+                    bail!("{}: cannot overwrite existing file", path.display());
+                }
+                // Devices like /dev/null can still be written to.
+                Ok(_) => {}
+                Err(_) => {
+                    file.create_new(true);
+                }
+            },
+            WriteMode::Truncate | WriteMode::Clobber => {
                 file.truncate(true);
             }
             WriteMode::Append => {
@@ -48,7 +165,6 @@ impl WriteMode {
             }
         }
 
-        let path = path.as_ref();
         Ok(file
             .open(path)
             .with_context(|_| path.display().to_string())?)
@@ -60,6 +176,83 @@ impl fmt::Display for WriteMode {
         match *self {
             WriteMode::Truncate => write!(f, ">"),
             WriteMode::Append => write!(f, ">>"),
+            WriteMode::Clobber => write!(f, ">|"),
         }
     }
 }
+
+/// Stores the contents of a here-document or here-string in an anonymous
+/// temporary file, positioned at the start so it can be read as stdin.
+pub fn here_document(contents: &[u8]) -> Result<File> {
+    let mut path = env::temp_dir();
+    let mut n = 0;
+    let mut file = loop {
+        path.push(format!(
+            "{}-heredoc-{}-{}",
+            env!("CARGO_PKG_NAME"),
+            process::id(),
+            n
+        ));
+        match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => break file,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                path.pop();
+                n += 1;
+            }
+            Err(e) => bail!("{}: {}", path.display(), e),
+        }
+    };
+    fs::remove_file(&path)?;
+    file.write_all(contents)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn noclobber() {
+        let path = env::temp_dir().join(format!("msh-noclobber-{}", process::id()));
+        let contents = || {
+            let mut contents = String::new();
+            File::open(&path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+
+        WriteMode::Truncate
+            .open(&path, true)
+            .unwrap()
+            .write_all(b"first")
+            .unwrap();
+        assert!(WriteMode::Truncate.open(&path, true).is_err());
+        assert_eq!(contents(), "first");
+
+        WriteMode::Append
+            .open(&path, true)
+            .unwrap()
+            .write_all(b" second")
+            .unwrap();
+        assert_eq!(contents(), "first second");
+
+        WriteMode::Clobber
+            .open(&path, true)
+            .unwrap()
+            .write_all(b"third")
+            .unwrap();
+        assert_eq!(contents(), "third");
+
+        assert!(WriteMode::Truncate.open("/dev/null", true).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,7 @@
-use libc;
+use std::ops::Not;
 
 /// The exit status of a command.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Status {
     Success,
     Failure,
@@ -13,6 +14,13 @@ impl Status {
             Status::Failure => false,
         }
     }
+
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Success => libc::EXIT_SUCCESS,
+            Status::Failure => libc::EXIT_FAILURE,
+        }
+    }
 }
 
 impl From<i32> for Status {
@@ -24,3 +32,14 @@ impl From<i32> for Status {
         }
     }
 }
+
+impl Not for Status {
+    type Output = Status;
+
+    fn not(self) -> Status {
+        match self {
+            Status::Success => Status::Failure,
+            Status::Failure => Status::Success,
+        }
+    }
+}
@@ -1,14 +1,16 @@
 use std::borrow::Cow;
 use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fmt;
-use std::iter::Cloned;
+use std::mem;
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::slice::Iter;
 
-use libc;
-
+use crate::arith;
 use crate::ast::NameValuePair;
-use crate::environment::Environment;
+use crate::brace;
+use crate::glob::{self, NoMatch};
+use crate::interpreter::Interpreter;
+use crate::parser;
 use crate::Result;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -73,13 +75,94 @@ impl Word {
         ))
     }
 
-    pub fn expand(&self, env: &Environment) -> Result<Cow<OsStr>> {
+    pub fn expand(&self, shell: &mut Interpreter) -> Result<Cow<'_, OsStr>> {
+        Ok(self.expand_with_pattern(shell)?.0)
+    }
+
+    /// Expands a word into a glob pattern, as in an arm of a `match`. Quoted
+    /// and escaped characters only match themselves.
+    pub fn expand_pattern(&self, shell: &mut Interpreter) -> Result<Vec<u8>> {
+        let (word, pattern) = self.expand_with_pattern(shell)?;
+        Ok(pattern.unwrap_or_else(|| escape_pattern(word.as_bytes())))
+    }
+
+    /// Expands a word that may stand for several arguments. Braces in an
+    /// unquoted word are expanded first, then each resulting word containing
+    /// a pattern is replaced by the paths it matches, if any.
+    pub fn expand_fields(&self, shell: &mut Interpreter) -> Result<Vec<Cow<'_, OsStr>>> {
+        let words = match self.expand_braces() {
+            Some(words) => words,
+            None => return self.expand_pathnames(shell),
+        };
+
+        let mut fields = Vec::new();
+        for word in words {
+            for field in word.expand_pathnames(shell)? {
+                fields.push(Cow::Owned(field.into_owned()));
+            }
+        }
+        Ok(fields)
+    }
+
+    /// The words an unquoted word with braces like `file.{c,h}` stands for,
+    /// or `None` if there's nothing to expand.
+    fn expand_braces(&self) -> Option<Vec<Word>> {
+        if self.quote.is_some() {
+            return None;
+        }
+
+        let words = brace::expand(self.as_bytes());
+        if words.len() == 1 && words[0] == self.as_bytes() {
+            return None;
+        }
+        Some(words.into_iter().map(Word::unquoted).collect())
+    }
+
+    fn expand_pathnames(&self, shell: &mut Interpreter) -> Result<Vec<Cow<'_, OsStr>>> {
+        let mut fields = Vec::new();
+        for (word, pattern) in self.expand_with_patterns(shell, true)? {
+            let pattern = match pattern {
+                Some(pattern) => pattern,
+                None => {
+                    fields.push(word);
+                    continue;
+                }
+            };
+
+            let glob = shell.options().glob();
+            let paths = glob.expand(shell.current_dir(), &pattern);
+            if !paths.is_empty() {
+                fields.extend(paths.into_iter().map(Cow::Owned));
+                continue;
+            }
+
+            match glob.no_match {
+                NoMatch::Literal => fields.push(word),
+                NoMatch::Empty => {}
+                NoMatch::Error => bail!("no matches found: {}", word.to_string_lossy()),
+            }
+        }
+        Ok(fields)
+    }
+
+    /// Expands the word, along with a glob pattern if it's unquoted and has
+    /// unescaped pattern characters.
+    fn expand_with_pattern(&self, shell: &mut Interpreter) -> Result<Field<'_>> {
+        Ok(self.expand_with_patterns(shell, false)?.remove(0))
+    }
+
+    /// Like `expand_with_pattern`, but with `split` the word becomes a field
+    /// for each positional parameter that `$@` stands for, as in `"$@"`.
+    fn expand_with_patterns(&self, shell: &mut Interpreter, split: bool) -> Result<Vec<Field<'_>>> {
         match self.quote {
-            Some(Quote::Single) => Ok(Cow::Borrowed(&self.value)),
-            Some(Quote::Double) => expand_env_vars(Cow::Borrowed(&self.value), env),
+            Some(Quote::Single) => Ok(vec![(Cow::Borrowed(&self.value), None)]),
+            Some(Quote::Double) => {
+                let fields = expand_env_vars(Cow::Borrowed(&self.value), self.quote, shell, split)?;
+                Ok(fields.into_iter().map(|(word, _)| (word, None)).collect())
+            }
             None => {
-                let word = expand_tilde(&self.value, env.home());
-                expand_env_vars(word, env)
+                let word = expand_tilde(&self.value, shell.env().home());
+                expand_env_vars(word, self.quote, shell, split)
             }
         }
     }
@@ -147,7 +230,7 @@ fn home_directory(username: &[u8]) -> Option<OsString> {
     Some(OsString::from_vec(c_str.to_bytes().to_vec()))
 }
 
-fn expand_tilde<H: AsRef<OsStr>>(word: &OsStr, home: H) -> Cow<OsStr> {
+fn expand_tilde<H: AsRef<OsStr>>(word: &OsStr, home: H) -> Cow<'_, OsStr> {
     let buf = word.as_bytes();
     if !buf.starts_with(b"~") {
         // No expansion necessary.
@@ -187,46 +270,175 @@ fn expand_tilde<H: AsRef<OsStr>>(word: &OsStr, home: H) -> Cow<OsStr> {
         })
 }
 
-fn expand_env_vars<'a>(word: Cow<'a, OsStr>, env: &Environment) -> Result<Cow<'a, OsStr>> {
-    match word.as_bytes().iter().position(|&b| b == b'$') {
-        Some(pos) => EnvExpander::new(word.as_bytes(), pos, env).expand(),
-        None => Ok(word),
+/// An expanded word along with the glob pattern it stands for, if any.
+type Field<'a> = (Cow<'a, OsStr>, Option<Vec<u8>>);
+
+/// Expands variables, escapes and command substitutions. Unquoted words are
+/// also returned as a glob pattern if they contain unescaped pattern bytes.
+/// There's a single field unless `split` lets `$@` stand for several.
+fn expand_env_vars<'a>(
+    word: Cow<'a, OsStr>,
+    quote: Option<Quote>,
+    shell: &mut Interpreter,
+    split: bool,
+) -> Result<Vec<Field<'a>>> {
+    let bytes = word.as_bytes();
+    match (0..bytes.len()).position(|i| {
+        bytes[i] == b'$'
+            || bytes[i] == b'\\'
+            || (quote.is_none() && is_process_substitution(&bytes[i..]))
+    }) {
+        Some(pos) => {
+            let mut expander = EnvExpander::new(word.as_bytes(), pos, quote, shell);
+            if split {
+                expander.fields = Some(Vec::new());
+            }
+            expander.expand()
+        }
+        None => {
+            let pattern = if quote.is_none() && glob::is_pattern(word.as_bytes()) {
+                Some(word.as_bytes().to_vec())
+            } else {
+                None
+            };
+            Ok(vec![(word, pattern)])
+        }
     }
 }
 
 struct EnvExpander<'a> {
     buf: Vec<u8>,
-    bytes: Cloned<Iter<'a, u8>>,
-    env: &'a Environment,
+    pattern: Vec<u8>,
+    /// The fields finished so far if `$@` stands for a field per positional
+    /// parameter, or `None` if it joins them with spaces.
+    fields: Option<Vec<(Vec<u8>, Vec<u8>)>>,
+    /// Whether `$@` expanded to no fields at all, so that `"$@"` with no
+    /// positional parameters is no word rather than an empty one.
+    no_arguments: bool,
+    bytes: Iter<'a, u8>,
+    quote: Option<Quote>,
+    shell: &'a mut Interpreter,
     peek: Option<u8>,
 }
 
 impl<'a> EnvExpander<'a> {
-    fn new(word: &'a [u8], pos: usize, env: &'a Environment) -> Self {
+    fn new(word: &'a [u8], pos: usize, quote: Option<Quote>, shell: &'a mut Interpreter) -> Self {
         Self {
             buf: word[0..pos].to_vec(),
-            bytes: word[pos + 1..].iter().cloned(),
-            env,
+            pattern: word[0..pos].to_vec(),
+            fields: None,
+            no_arguments: false,
+            bytes: word[pos..].iter(),
+            quote,
+            shell,
             peek: None,
         }
     }
 
-    fn expand<'word>(mut self) -> Result<Cow<'word, OsStr>> {
-        // The starting position is the byte after the first $.
-        self.expand_variable()?;
+    fn expand<'word>(mut self) -> Result<Vec<Field<'word>>> {
+        self.expand_all()?;
+
+        let mut fields = self.fields.take().unwrap_or_default();
+        if !(self.no_arguments && fields.is_empty() && self.buf.is_empty()) {
+            fields.push((self.buf, self.pattern));
+        }
+
+        let quote = self.quote;
+        Ok(fields
+            .into_iter()
+            .map(|(buf, pattern)| {
+                let pattern = if quote.is_none() && glob::is_pattern(&pattern) {
+                    Some(pattern)
+                } else {
+                    None
+                };
+                (Cow::Owned(OsString::from_vec(buf)), pattern)
+            })
+            .collect())
+    }
 
+    fn expand_all(&mut self) -> Result<()> {
         while let Some(byte) = self.next_byte() {
-            if byte == b'$' {
-                self.expand_variable()?;
+            match byte {
+                b'$' => self.expand_variable()?,
+                b'\\' => self.expand_escape(),
+                b'<' | b'>'
+                    if self.quote.is_none()
+                        && self.peek.is_none()
+                        && self.bytes.as_slice().first() == Some(&b'(') =>
+                {
+                    self.expand_process_substitution(byte)?
+                }
+                _ => self.push(byte),
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends a byte that keeps any special meaning it has in a pattern.
+    fn push(&mut self, byte: u8) {
+        self.buf.push(byte);
+        self.pattern.push(byte);
+    }
+
+    /// Appends a byte that always matches itself in a pattern.
+    fn push_escaped(&mut self, byte: u8) {
+        if glob::is_special(byte) {
+            self.pattern.push(b'\\');
+        }
+        self.buf.push(byte);
+        self.pattern.push(byte);
+    }
+
+    /// Appends the value of a variable or command substitution. Like in other
+    /// shells, pattern characters in the value are active when unquoted.
+    fn push_value(&mut self, value: &[u8]) {
+        for &byte in value {
+            if byte == b'\\' {
+                self.push_escaped(byte);
             } else {
-                self.buf.push(byte);
+                self.push(byte);
+            }
+        }
+    }
+
+    /// Appends the positional parameters for `$@`, each in a field of its own
+    /// if the word is being split into fields.
+    fn push_arguments(&mut self) {
+        if self.fields.is_none() {
+            let value = self.var(b"@").unwrap_or_default();
+            self.push_value(&value);
+            return;
+        }
+
+        let arguments = self.shell.env().positional().to_vec();
+        self.no_arguments |= arguments.is_empty();
+        for (i, argument) in arguments.iter().enumerate() {
+            if i > 0 {
+                let field = (mem::take(&mut self.buf), mem::take(&mut self.pattern));
+                self.fields.as_mut().unwrap().push(field);
             }
+            self.push_value(argument.as_bytes());
+        }
+    }
+
+    /// Unquoted, a backslash escapes any byte. Inside double quotes it only
+    /// escapes bytes that would otherwise be special there.
+    fn expand_escape(&mut self) {
+        match self.next_byte() {
+            Some(byte) if self.quote.is_none() || is_double_quote_escapable(byte) => {
+                self.push_escaped(byte)
+            }
+            Some(byte) => {
+                self.push_escaped(b'\\');
+                self.push_escaped(byte);
+            }
+            None => self.push_escaped(b'\\'),
         }
-        Ok(Cow::Owned(OsString::from_vec(self.buf)))
     }
 
     fn next_byte(&mut self) -> Option<u8> {
-        self.peek.take().or_else(|| self.bytes.next())
+        self.peek.take().or_else(|| self.bytes.next().cloned())
     }
 
     fn push_byte(&mut self, byte: u8) {
@@ -237,28 +449,240 @@ impl<'a> EnvExpander<'a> {
     fn expand_variable(&mut self) -> Result<()> {
         if let Some(byte) = self.next_byte() {
             let mut name = Vec::new();
-            if byte == b'{' {
-                if !self.consume_while(&mut name, |b| b != b'}', false) {
+            if byte == b'(' {
+                return self.expand_substitution();
+            } else if byte == b'{' {
+                return self.expand_parameter();
+            } else if is_special_name_byte(byte) {
+                // Special parameters are a single byte, so $10 is $1 followed by 0.
+                name.push(byte);
+            } else {
+                self.push_byte(byte);
+                self.consume_while(&mut name, is_valid_name_byte, true);
+            }
+            check_name(&name)?;
+            if name == b"@" {
+                self.push_arguments();
+                return Ok(());
+            }
+            let value = self.var(&name).unwrap_or_default();
+            self.push_value(&value);
+        } else {
+            self.push(b'$');
+        }
+        Ok(())
+    }
+
+    /// Expands `${NAME}`, optionally followed by an operator that supplies a
+    /// default, checks that it's set, or strips or replaces part of it.
+    fn expand_parameter(&mut self) -> Result<()> {
+        assert!(self.peek.is_none());
+        let rest = self.bytes.as_slice();
+        let len = match find_closing_brace(rest) {
+            Some(len) => len,
+            None => bail!(
+                "missing variable closing brace{}",
+                if rest.is_empty() {
+                    "".into()
+                } else {
+                    format!(" around: {}", String::from_utf8_lossy(rest))
+                }
+            ),
+        };
+        self.bytes = rest[len + 1..].iter();
+        let expr = &rest[..len];
+
+        if expr.len() > 1 && expr[0] == b'#' {
+            let name = &expr[1..];
+            check_name(name)?;
+            let value = self.var(name).unwrap_or_default();
+            let len = String::from_utf8_lossy(&value).chars().count();
+            self.push_value(len.to_string().as_bytes());
+            return Ok(());
+        }
+
+        if expr == b"@" {
+            self.push_arguments();
+            return Ok(());
+        }
+
+        let (name, expr) = expr.split_at(parameter_name_len(expr));
+        check_name(name)?;
+        let value = self.var(name);
+
+        let (colon, expr) = match expr.split_first() {
+            Some((b':', expr)) => (true, expr),
+            _ => (false, expr),
+        };
+        let (op, operand) = match expr.split_first() {
+            Some((&op, operand)) => (op, operand),
+            None if colon => bail!("bad substitution: ${{{}:}}", String::from_utf8_lossy(name)),
+            None => {
+                self.push_value(&value.unwrap_or_default());
+                return Ok(());
+            }
+        };
+        // With a colon, an empty value counts as unset.
+        let value = value.filter(|value| !colon || !value.is_empty());
+
+        match op {
+            b'-' => match value {
+                Some(value) => self.push_value(&value),
+                None => self.push_operand(operand)?,
+            },
+            b'=' => match value {
+                Some(value) => self.push_value(&value),
+                None => {
+                    if !is_valid_name(name) {
+                        bail!("cannot assign to ${}", String::from_utf8_lossy(name));
+                    }
+                    let (default, _) = self.expand_operand(operand)?;
+                    self.shell.env_mut().set(
+                        OsString::from_vec(name.to_vec()),
+                        OsString::from_vec(default.clone()),
+                    );
+                    self.push_value(&default);
+                }
+            },
+            b'?' => match value {
+                Some(value) => self.push_value(&value),
+                None => {
+                    let (message, _) = self.expand_operand(operand)?;
                     bail!(
-                        "missing variable closing brace{}",
-                        if name.is_empty() {
-                            "".into()
+                        "{}: {}",
+                        String::from_utf8_lossy(name),
+                        if message.is_empty() {
+                            "parameter null or not set".into()
                         } else {
-                            format!(" around: {}", String::from_utf8_lossy(&name))
+                            String::from_utf8_lossy(&message)
                         }
                     );
                 }
-            } else {
-                self.push_byte(byte);
-                self.consume_while(&mut name, is_valid_name_byte, true);
+            },
+            b'+' => {
+                if value.is_some() {
+                    self.push_operand(operand)?;
+                }
             }
-            if !is_valid_name(&name) {
-                bail!("invalid variable name: {}", String::from_utf8_lossy(&name));
+            _ if colon => bail!(
+                "bad substitution: ${{{}:{}}}",
+                String::from_utf8_lossy(name),
+                String::from_utf8_lossy(expr)
+            ),
+            b'#' | b'%' => {
+                let longest = operand.first() == Some(&op);
+                let operand = if longest { &operand[1..] } else { operand };
+                let (_, pattern) = self.expand_operand(operand)?;
+                let value = value.unwrap_or_default();
+                let stripped = if op == b'#' {
+                    strip_prefix(&value, &pattern, longest)
+                } else {
+                    strip_suffix(&value, &pattern, longest)
+                };
+                self.push_value(stripped);
             }
-            self.append_var(&name);
-        } else {
-            self.buf.push(b'$');
+            b'/' => {
+                let all = operand.first() == Some(&b'/');
+                let operand = if all { &operand[1..] } else { operand };
+                let (pattern, replacement) = match find_unescaped(operand, b'/') {
+                    Some(pos) => (&operand[..pos], &operand[pos + 1..]),
+                    None => (operand, &[][..]),
+                };
+                let (_, pattern) = self.expand_operand(pattern)?;
+                let (replacement, _) = self.expand_operand(replacement)?;
+                let value = value.unwrap_or_default();
+                self.push_value(&replace(&value, &pattern, &replacement, all));
+            }
+            _ => bail!(
+                "bad substitution: ${{{}{}}}",
+                String::from_utf8_lossy(name),
+                String::from_utf8_lossy(expr)
+            ),
+        }
+        Ok(())
+    }
+
+    /// Expands the word after a parameter operator, returning its value along
+    /// with the pattern it stands for. A word that's entirely quoted is taken
+    /// literally, like a quoted word anywhere else.
+    fn expand_operand(&mut self, operand: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+        let (operand, quote) = match parse_quoted_word(operand) {
+            Some(Word {
+                value,
+                quote: Some(Quote::Single),
+            }) => {
+                let value = value.into_vec();
+                let pattern = escape_pattern(&value);
+                return Ok((value, pattern));
+            }
+            Some(word) => (word.value.into_vec(), word.quote),
+            None => (operand.to_vec(), self.quote),
+        };
+
+        let mut expander = EnvExpander::new(&operand, 0, quote, self.shell);
+        expander.expand_all()?;
+        let (value, mut pattern) = (expander.buf, expander.pattern);
+        if quote.is_some() && self.quote.is_none() {
+            pattern = escape_pattern(&value);
+        }
+        Ok((value, pattern))
+    }
+
+    /// Appends the expanded word after a parameter operator.
+    fn push_operand(&mut self, operand: &[u8]) -> Result<()> {
+        let (value, pattern) = self.expand_operand(operand)?;
+        self.buf.extend(value);
+        self.pattern.extend(pattern);
+        Ok(())
+    }
+
+    fn expand_substitution(&mut self) -> Result<()> {
+        assert!(self.peek.is_none());
+        let rest = self.bytes.as_slice();
+        let len = match find_closing_paren(rest) {
+            Some(len) => len,
+            None => bail!("missing closing ')' for command substitution"),
+        };
+        self.bytes = rest[len + 1..].iter();
+
+        let inner = &rest[..len];
+        if inner.len() >= 2 && inner[0] == b'(' && find_closing_paren(&inner[1..]) == Some(len - 2)
+        {
+            return self.expand_arithmetic(&inner[1..len - 1]);
+        }
+
+        let program = parser::parse(inner)?;
+        let mut output = self.shell.substitute(&program)?;
+        while output.last() == Some(&b'\n') {
+            output.pop();
         }
+        self.push_value(&output);
+        Ok(())
+    }
+
+    /// Expands `<(...)` or `>(...)` to a path connected to the program.
+    fn expand_process_substitution(&mut self, op: u8) -> Result<()> {
+        let rest = &self.bytes.as_slice()[1..];
+        let len = match find_closing_paren(rest) {
+            Some(len) => len,
+            None => bail!("missing closing ')' for process substitution"),
+        };
+        self.bytes = rest[len + 1..].iter();
+
+        let program = parser::parse(&rest[..len])?;
+        let path = self.shell.substitute_process(&program, op == b'<')?;
+        self.push_value(path.as_bytes());
+        Ok(())
+    }
+
+    /// Expands `$(( expr ))`. Parameters and substitutions in the expression
+    /// are expanded before it's evaluated.
+    fn expand_arithmetic(&mut self, expr: &[u8]) -> Result<()> {
+        let mut expander = EnvExpander::new(expr, 0, Some(Quote::Double), self.shell);
+        expander.expand_all()?;
+        let expr = expander.buf;
+        let value = arith::evaluate(&expr, self.shell.env())?;
+        self.push_value(value.to_string().as_bytes());
         Ok(())
     }
 
@@ -279,14 +703,46 @@ impl<'a> EnvExpander<'a> {
         false
     }
 
-    fn append_var(&mut self, name: &[u8]) {
-        if let Some(value) = self.env.get(OsStr::from_bytes(name)) {
-            self.buf.extend(value.as_bytes());
-        }
+    /// The value of a variable or special parameter, or `None` if it's unset.
+    fn var(&self, name: &[u8]) -> Option<Vec<u8>> {
+        let value = match name {
+            b"@" => {
+                let mut value = Vec::new();
+                for (i, argument) in self.shell.env().positional().iter().enumerate() {
+                    if i > 0 {
+                        value.push(b' ');
+                    }
+                    value.extend(argument.as_bytes());
+                }
+                value
+            }
+            b"#" => self.shell.env().positional().len().to_string().into_bytes(),
+            b"0" => env!("CARGO_PKG_NAME").as_bytes().to_vec(),
+            b"!" => {
+                let pid = self.shell.last_background()?;
+                pid.to_string().into_bytes()
+            }
+            _ if name[0].is_ascii_digit() => {
+                return String::from_utf8_lossy(name)
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| n.checked_sub(1))
+                    .and_then(|i| self.shell.env().positional().get(i))
+                    .map(|argument| argument.as_bytes().to_vec());
+            }
+            _ => {
+                return self
+                    .shell
+                    .env()
+                    .get(OsStr::from_bytes(name))
+                    .map(|value| value.as_bytes().to_vec());
+            }
+        };
+        Some(value)
     }
 }
 
-fn is_valid_name(input: &[u8]) -> bool {
+pub fn is_valid_name(input: &[u8]) -> bool {
     !input.is_empty()
         && is_valid_first_byte(input[0])
         && input[1..].iter().cloned().all(is_valid_name_byte)
@@ -300,6 +756,160 @@ fn is_valid_name_byte(byte: u8) -> bool {
     is_valid_first_byte(byte) || byte.is_ascii_digit()
 }
 
+/// Finds the `)` that closes a `$(` whose contents start at `bytes[0]`,
+/// skipping over nested parentheses, quotes and escapes.
+pub fn find_closing_paren(bytes: &[u8]) -> Option<usize> {
+    find_closing(bytes, b'(', b')')
+}
+
+/// Finds the `}` that closes a `${` whose contents start at `bytes[0]`.
+pub fn find_closing_brace(bytes: &[u8]) -> Option<usize> {
+    find_closing(bytes, b'{', b'}')
+}
+
+fn find_closing(bytes: &[u8], open: u8, close: u8) -> Option<usize> {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut iter = bytes.iter().enumerate();
+
+    while let Some((i, &byte)) = iter.next() {
+        match (quote, byte) {
+            (Some(b'\''), b'\'') => quote = None,
+            (Some(b'\''), _) => {}
+            (_, b'\\') => {
+                iter.next();
+            }
+            (Some(b'"'), b'"') => quote = None,
+            (Some(_), _) => {}
+            (None, b'\'') | (None, b'"') => quote = Some(byte),
+            (None, _) if byte == open => depth += 1,
+            (None, _) if byte == close && depth == 0 => return Some(i),
+            (None, _) if byte == close => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn is_process_substitution(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"<(") || bytes.starts_with(b">(")
+}
+
+fn is_double_quote_escapable(byte: u8) -> bool {
+    byte == b'$' || byte == b'`' || byte == b'"' || byte == b'\\'
+}
+
+/// Positional parameters ($1, ${10}), all arguments ($@) and their count ($#).
+fn is_special_name(input: &[u8]) -> bool {
+    match input {
+        b"@" | b"#" | b"!" => true,
+        _ => !input.is_empty() && input.iter().all(u8::is_ascii_digit),
+    }
+}
+
+fn is_special_name_byte(byte: u8) -> bool {
+    byte == b'@' || byte == b'#' || byte == b'!' || byte.is_ascii_digit()
+}
+
+fn check_name(name: &[u8]) -> Result<()> {
+    if !is_valid_name(name) && !is_special_name(name) {
+        bail!("invalid variable name: {}", String::from_utf8_lossy(name));
+    }
+    Ok(())
+}
+
+/// The length of the parameter name at the start of a `${...}` expansion.
+/// Positional parameters may have several digits inside braces.
+fn parameter_name_len(expr: &[u8]) -> usize {
+    match expr.first() {
+        Some(b'@') | Some(b'#') | Some(b'!') => 1,
+        Some(byte) if byte.is_ascii_digit() => {
+            expr.iter().take_while(|b| b.is_ascii_digit()).count()
+        }
+        _ => expr.iter().take_while(|&&b| is_valid_name_byte(b)).count(),
+    }
+}
+
+fn find_unescaped(bytes: &[u8], needle: u8) -> Option<usize> {
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            byte if byte == needle => return Some(i),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn escape_pattern(value: &[u8]) -> Vec<u8> {
+    let mut pattern = Vec::with_capacity(value.len());
+    for &byte in value {
+        if glob::is_special(byte) {
+            pattern.push(b'\\');
+        }
+        pattern.push(byte);
+    }
+    pattern
+}
+
+/// Removes the shortest or longest prefix of `value` matching `pattern`.
+fn strip_prefix<'a>(value: &'a [u8], pattern: &[u8], longest: bool) -> &'a [u8] {
+    let mut ends: Box<dyn Iterator<Item = usize>> = if longest {
+        Box::new((0..=value.len()).rev())
+    } else {
+        Box::new(0..=value.len())
+    };
+    match ends.find(|&end| glob::matches(pattern, &value[..end])) {
+        Some(end) => &value[end..],
+        None => value,
+    }
+}
+
+/// Removes the shortest or longest suffix of `value` matching `pattern`.
+fn strip_suffix<'a>(value: &'a [u8], pattern: &[u8], longest: bool) -> &'a [u8] {
+    let mut starts: Box<dyn Iterator<Item = usize>> = if longest {
+        Box::new(0..=value.len())
+    } else {
+        Box::new((0..=value.len()).rev())
+    };
+    match starts.find(|&start| glob::matches(pattern, &value[start..])) {
+        Some(start) => &value[..start],
+        None => value,
+    }
+}
+
+/// Replaces the first, or with `all` every, longest match of `pattern`.
+fn replace(value: &[u8], pattern: &[u8], replacement: &[u8], all: bool) -> Vec<u8> {
+    if pattern.is_empty() {
+        return value.to_vec();
+    }
+
+    let mut replaced = Vec::with_capacity(value.len());
+    let mut start = 0;
+    while start < value.len() {
+        let end = (start + 1..=value.len())
+            .rev()
+            .find(|&end| glob::matches(pattern, &value[start..end]));
+        match end {
+            Some(end) => {
+                replaced.extend_from_slice(replacement);
+                start = end;
+                if !all {
+                    break;
+                }
+            }
+            None => {
+                replaced.push(value[start]);
+                start += 1;
+            }
+        }
+    }
+    replaced.extend_from_slice(&value[start..]);
+    replaced
+}
+
 fn parse_quoted_word(value: &[u8]) -> Option<Word> {
     let len = value.len();
     if len < 2 {
@@ -322,9 +932,11 @@ fn parse_quoted_word(value: &[u8]) -> Option<Word> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::environment::Environment;
     use std::env;
+    use std::fs::{self, File};
+    use std::io::Read;
     use std::path::Path;
+    use std::process;
 
     fn home() -> OsString {
         env::var_os("HOME").unwrap()
@@ -336,31 +948,34 @@ mod tests {
 
     #[test]
     fn tilde_expansion() {
-        let env = Environment::new();
-        assert_eq!(Word::unquoted("~").expand(&env).unwrap(), home());
+        let mut shell = Interpreter::new().unwrap();
+        assert_eq!(Word::unquoted("~").expand(&mut shell).unwrap(), home());
         assert_eq!(
-            Word::unquoted("~/Desktop").expand(&env).unwrap(),
+            Word::unquoted("~/Desktop").expand(&mut shell).unwrap(),
             Path::new(&home()).join("Desktop"),
         );
 
-        for quote in vec![Quote::Single, Quote::Double] {
-            assert_eq!(Word::new("~", quote).expand(&env).unwrap(), OsStr::new("~"));
+        for quote in [Quote::Single, Quote::Double] {
+            assert_eq!(
+                Word::new("~", quote).expand(&mut shell).unwrap(),
+                OsStr::new("~")
+            );
         }
     }
 
     #[test]
     fn tilde_expansion_user() {
-        let env = Environment::new();
+        let mut shell = Interpreter::new().unwrap();
         let mut input = OsString::new();
         input.push("~");
         input.push(user());
         assert_eq!(
-            Word::unquoted(input.as_bytes()).expand(&env).unwrap(),
+            Word::unquoted(input.as_bytes()).expand(&mut shell).unwrap(),
             home()
         );
         input.push("/Downloads");
         assert_eq!(
-            Word::unquoted(input.as_bytes()).expand(&env).unwrap(),
+            Word::unquoted(input.as_bytes()).expand(&mut shell).unwrap(),
             Path::new(&home()).join("Downloads"),
         );
     }
@@ -384,18 +999,318 @@ mod tests {
             ));
         }
 
-        let env = Environment::new();
+        let mut shell = Interpreter::new().unwrap();
         for (input, expected) in tests {
-            for quote in vec![None, Some(Quote::Single), Some(Quote::Double)] {
+            for quote in [None, Some(Quote::Single), Some(Quote::Double)] {
                 let word = Word::new(input.as_bytes(), quote);
                 match quote {
-                    None | Some(Quote::Double) => assert_eq!(word.expand(&env).unwrap(), expected),
-                    Some(Quote::Single) => assert_eq!(word.expand(&env).unwrap(), input),
+                    None | Some(Quote::Double) => {
+                        assert_eq!(word.expand(&mut shell).unwrap(), expected)
+                    }
+                    Some(Quote::Single) => assert_eq!(word.expand(&mut shell).unwrap(), input),
                 }
             }
         }
     }
 
+    #[test]
+    fn parameter_operators() {
+        let mut shell = Interpreter::new().unwrap();
+        shell.env_mut().set("FILE".into(), "archive.tar.gz".into());
+        shell.env_mut().set("EMPTY".into(), "".into());
+
+        let tests = [
+            ("${UNSET:-default}", "default"),
+            ("${EMPTY:-default}", "default"),
+            ("${EMPTY-default}", ""),
+            ("${FILE:-default}", "archive.tar.gz"),
+            ("${UNSET:-$FILE}", "archive.tar.gz"),
+            ("${UNSET:-'$FILE'}", "$FILE"),
+            ("${FILE:+alt}", "alt"),
+            ("${EMPTY:+alt}", ""),
+            ("${EMPTY+alt}", "alt"),
+            ("${#FILE}", "14"),
+            ("${#UNSET}", "0"),
+            ("${FILE#*.}", "tar.gz"),
+            ("${FILE##*.}", "gz"),
+            ("${FILE%.*}", "archive.tar"),
+            ("${FILE%%.*}", "archive"),
+            ("${FILE%.zip}", "archive.tar.gz"),
+            ("${FILE/a/A}", "Archive.tar.gz"),
+            ("${FILE//a/A}", "Archive.tAr.gz"),
+            ("${FILE/.t*z}", "archive"),
+            (r"${FILE/\*/x}", "archive.tar.gz"),
+        ];
+        for &(input, expected) in &tests {
+            let word = Word::unquoted(input);
+            assert_eq!(word.expand(&mut shell).unwrap(), OsStr::new(expected));
+        }
+
+        let word = Word::unquoted("${NEW:=assigned}");
+        assert_eq!(word.expand(&mut shell).unwrap(), OsStr::new("assigned"));
+        assert_eq!(shell.env().get(OsStr::new("NEW")).unwrap(), "assigned");
+
+        for input in &[
+            "${UNSET:?}",
+            "${EMPTY:?message}",
+            "${1:=x}",
+            "${FILE:}",
+            "${FILE!}",
+        ] {
+            assert!(Word::unquoted(*input).expand(&mut shell).is_err());
+        }
+    }
+
+    #[test]
+    fn arithmetic_expansion() {
+        let mut shell = Interpreter::new().unwrap();
+        shell.env_mut().set("N".into(), "4".into());
+
+        let tests = [
+            ("$((N * 2 + 1))", "9"),
+            ("$(( ($N - 1) * 2 ))", "6"),
+            ("x$((N > 3))y", "x1y"),
+            ("$((N / 3))$((N % 3))", "11"),
+        ];
+        for &(input, expected) in &tests {
+            let word = Word::unquoted(input);
+            assert_eq!(word.expand(&mut shell).unwrap(), OsStr::new(expected));
+        }
+        let word = Word::new("$((N - 5))", Quote::Double);
+        assert_eq!(word.expand(&mut shell).unwrap(), OsStr::new("-1"));
+
+        assert!(Word::unquoted("$((N / 0))").expand(&mut shell).is_err());
+        assert!(Word::unquoted("$((N +))").expand(&mut shell).is_err());
+    }
+
+    #[test]
+    fn positional_parameter_expansion() {
+        let mut shell = Interpreter::new().unwrap();
+        shell.env_mut().set_positional(
+            "a b c d e f g h i j"
+                .split(' ')
+                .map(OsString::from)
+                .collect(),
+        );
+
+        let tests = vec![
+            ("$1", "a"),
+            ("$2-$3", "b-c"),
+            ("$10", "a0"),
+            ("${10}", "j"),
+            ("$#", "10"),
+            ("$@", "a b c d e f g h i j"),
+        ];
+        for (input, expected) in tests {
+            assert_eq!(
+                Word::new(input, Quote::Double).expand(&mut shell).unwrap(),
+                OsStr::new(expected),
+            );
+        }
+
+        shell.env_mut().set_positional(Vec::new());
+        assert_eq!(
+            Word::unquoted("$1$#").expand(&mut shell).unwrap(),
+            OsStr::new("0")
+        );
+        assert_eq!(
+            Word::unquoted("[${00}]").expand(&mut shell).unwrap(),
+            OsStr::new("[]")
+        );
+    }
+
+    #[test]
+    fn positional_parameter_fields() {
+        let mut shell = Interpreter::new().unwrap();
+        shell
+            .env_mut()
+            .set_positional(vec!["a b".into(), "c".into()]);
+
+        let tests = vec![
+            (Word::new("$@", Quote::Double), vec!["a b", "c"]),
+            (Word::new("<${@}>", Quote::Double), vec!["<a b", "c>"]),
+            (Word::unquoted("$@"), vec!["a b", "c"]),
+            (Word::new("$@", Quote::Single), vec!["$@"]),
+        ];
+        for (word, expected) in tests {
+            let expected: Vec<_> = expected.into_iter().map(OsStr::new).collect();
+            assert_eq!(word.expand_fields(&mut shell).unwrap(), expected);
+        }
+        assert_eq!(
+            Word::new("$@", Quote::Double).expand(&mut shell).unwrap(),
+            OsStr::new("a b c")
+        );
+
+        shell.env_mut().set_positional(Vec::new());
+        assert!(Word::new("$@", Quote::Double)
+            .expand_fields(&mut shell)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            Word::new("x$@", Quote::Double)
+                .expand_fields(&mut shell)
+                .unwrap(),
+            vec![OsStr::new("x")]
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        env::set_var("ESCAPED", "value");
+        let mut shell = Interpreter::new().unwrap();
+
+        let tests = vec![
+            (Word::unquoted(r"a\ b"), r"a b"),
+            (Word::unquoted(r"\$ESCAPED"), r"$ESCAPED"),
+            (Word::unquoted(r"\\$ESCAPED"), r"\value"),
+            (Word::unquoted(r"\~"), r"~"),
+            (Word::unquoted(r"\n"), r"n"),
+            (Word::unquoted(r"trailing\"), r"trailing\"),
+            (Word::new(r"\$ESCAPED", Quote::Double), r"$ESCAPED"),
+            (Word::new(r#"say \"hi\""#, Quote::Double), r#"say "hi""#),
+            (Word::new(r"C:\\Users", Quote::Double), r"C:\Users"),
+            (Word::new(r"\n", Quote::Double), r"\n"),
+            (Word::new(r"\$ESCAPED", Quote::Single), r"\$ESCAPED"),
+        ];
+        for (word, expected) in tests {
+            assert_eq!(word.expand(&mut shell).unwrap(), OsStr::new(expected));
+        }
+    }
+
+    #[test]
+    fn command_substitution() {
+        let mut shell = Interpreter::new().unwrap();
+        let tests = vec![
+            (Word::unquoted("$(echo hello)"), "hello"),
+            (Word::unquoted("v$(echo 1; echo 2)"), "v1\n2"),
+            (Word::new("a $(printf 'b\\n\\n') c", Quote::Double), "a b c"),
+            (Word::unquoted("$(echo $(echo nested))"), "nested"),
+            (Word::unquoted("$(echo ')')"), ")"),
+            (Word::unquoted("\\$(echo hello)"), "$(echo hello)"),
+            (Word::new("$(echo hello)", Quote::Single), "$(echo hello)"),
+        ];
+        for (word, expected) in tests {
+            assert_eq!(word.expand(&mut shell).unwrap(), OsStr::new(expected));
+        }
+
+        assert!(Word::unquoted("$(echo hello").expand(&mut shell).is_err());
+    }
+
+    #[test]
+    fn process_substitution() {
+        let mut shell = Interpreter::new().unwrap();
+        let path = Word::unquoted("<(echo hello)")
+            .expand(&mut shell)
+            .unwrap()
+            .into_owned();
+        assert!(path.as_bytes().starts_with(b"/dev/fd/"));
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hello\n");
+
+        for quote in [Quote::Single, Quote::Double] {
+            let word = Word::new("<(echo hello)", quote);
+            assert_eq!(
+                word.expand(&mut shell).unwrap(),
+                OsStr::new("<(echo hello)")
+            );
+        }
+        assert!(Word::unquoted("<(echo hello").expand(&mut shell).is_err());
+    }
+
+    #[test]
+    fn pathname_expansion() {
+        let dir = env::temp_dir().join(format!("msh-word-glob-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in &["b.rs", "a.rs", "c.txt"] {
+            File::create(dir.join(name)).unwrap();
+        }
+
+        let mut shell = Interpreter::new().unwrap();
+        shell
+            .env_mut()
+            .set("DIR".into(), dir.clone().into_os_string());
+        let path = |name: &str| dir.join(name).into_os_string();
+
+        let fields = |word: Word, shell: &mut Interpreter| -> Vec<OsString> {
+            word.expand_fields(shell)
+                .unwrap()
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect()
+        };
+        assert_eq!(
+            fields(Word::unquoted("$DIR/*.rs"), &mut shell),
+            vec![path("a.rs"), path("b.rs")],
+        );
+        assert_eq!(
+            fields(Word::unquoted("$DIR/?.txt"), &mut shell),
+            vec![path("c.txt")],
+        );
+        assert_eq!(
+            fields(Word::unquoted("$DIR/*.md"), &mut shell),
+            vec![path("*.md")],
+        );
+        assert_eq!(
+            fields(Word::unquoted(r"$DIR/\*.rs"), &mut shell),
+            vec![path("*.rs")],
+        );
+        for quote in [Quote::Single, Quote::Double] {
+            let word = Word::new(format!("{}/*.rs", dir.display()), quote);
+            assert_eq!(fields(word, &mut shell), vec![path("*.rs")]);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn brace_expansion() {
+        let mut shell = Interpreter::new().unwrap();
+        shell.env_mut().set("X".into(), "x".into());
+
+        let fields = |word: Word, shell: &mut Interpreter| -> Vec<OsString> {
+            word.expand_fields(shell)
+                .unwrap()
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect()
+        };
+        assert_eq!(
+            fields(Word::unquoted("a{$X,b}"), &mut shell),
+            vec![OsString::from("ax"), OsString::from("ab")],
+        );
+        let mut home = home();
+        home.push("/src");
+        assert_eq!(
+            fields(Word::unquoted("{~/src,a{1..2}}"), &mut shell),
+            vec![home, OsString::from("a1"), OsString::from("a2")],
+        );
+        assert_eq!(
+            fields(Word::new("{a,b}", Quote::Double), &mut shell),
+            vec![OsString::from("{a,b}")],
+        );
+    }
+
+    #[test]
+    fn unmatched_patterns() {
+        let mut shell = Interpreter::new().unwrap();
+        let word = Word::unquoted("/nonexistent/*.rs");
+
+        assert_eq!(
+            word.expand_fields(&mut shell).unwrap(),
+            vec![OsStr::new("/nonexistent/*.rs")],
+        );
+
+        shell.options_mut().nullglob = true;
+        assert!(word.expand_fields(&mut shell).unwrap().is_empty());
+
+        shell.options_mut().failglob = true;
+        assert!(word.expand_fields(&mut shell).is_err());
+    }
+
     #[test]
     fn name_value_pairs() {
         let word = Word::new("FOO=bar", None);
@@ -407,7 +1322,7 @@ mod tests {
             )),
         );
 
-        for quote in vec![Quote::Single, Quote::Double] {
+        for quote in [Quote::Single, Quote::Double] {
             let word = Word::new(format!("FOO={quote}bar{quote}", quote = quote), None);
             assert_eq!(
                 word.parse_name_value_pair(),